@@ -6,11 +6,11 @@ pub mod zobrist;
 use self::{bitboard::*, gamestate::Gamestate, piece::*};
 
 use crate::move_gen::{
-    chess_move::Move, generate_attacks, generate_legal_moves, move_list::MoveList,
+    chess_move::Move, find_attackers, generate_attacks, generate_legal_moves, move_list::MoveList,
 };
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Board {
     pieces_bb: [[u64; 6]; 2],
     colors_bb: [u64; 2],
@@ -183,6 +183,17 @@ impl Board {
             self.current_zobrist_key ^= zobrist::piece(moved_color, promotion_type, land_index);
         }
 
+        // a rook captured on its starting square takes the enemy castling right on that side with it
+        if (land_index == 0 && enemy_color == PieceColor::White)
+            || (land_index == 56 && enemy_color == PieceColor::Black)
+        {
+            self.current_gamestate.remove_castle_queenside(enemy_color);
+        } else if (land_index == 7 && enemy_color == PieceColor::White)
+            || (land_index == 63 && enemy_color == PieceColor::Black)
+        {
+            self.current_gamestate.remove_castle_kingside(enemy_color);
+        }
+
         // xor in the new castling rights
         self.current_zobrist_key ^= zobrist::castling(self.current_gamestate.get_castling_rights());
 
@@ -251,6 +262,9 @@ impl Board {
 
         self.current_zobrist_key ^= zobrist::color_to_move();
         self.current_gamestate.zobrist_key = self.current_zobrist_key;
+
+        #[cfg(feature = "board-invariants")]
+        self.assert_consistent();
     }
 
     /// THIS METHOD CHANGES COLOR_TO_MOVE
@@ -308,6 +322,48 @@ impl Board {
         self.current_gamestate = self.gamestate_stack.pop().unwrap();
         self.current_zobrist_key = self.current_gamestate.zobrist_key;
         self.color_to_move = !self.color_to_move;
+
+        #[cfg(feature = "board-invariants")]
+        self.assert_consistent();
+    }
+
+    /// Passes the turn to the other side, used by null move pruning
+    pub fn make_null_move(&mut self) {
+        self.current_zobrist_key ^=
+            zobrist::enpassant_file(self.current_gamestate.enpassant_square);
+
+        self.gamestate_stack.push(self.current_gamestate); // push old gamestate
+
+        self.current_gamestate.last_piece_captured = None;
+        self.current_gamestate.enpassant_square = None;
+        // a repetition can't span a null move, resetting the clock stops is_threefold_repetition here
+        self.current_gamestate.halfmove_clock = 0;
+
+        self.color_to_move = !self.color_to_move;
+
+        self.current_zobrist_key ^= zobrist::color_to_move();
+        self.current_gamestate.zobrist_key = self.current_zobrist_key;
+
+        #[cfg(feature = "board-invariants")]
+        self.assert_consistent();
+    }
+
+    pub fn unmake_null_move(&mut self) {
+        self.current_gamestate = self.gamestate_stack.pop().unwrap();
+        self.current_zobrist_key = self.current_gamestate.zobrist_key;
+        self.color_to_move = !self.color_to_move;
+
+        #[cfg(feature = "board-invariants")]
+        self.assert_consistent();
+    }
+
+    #[inline]
+    pub fn has_non_pawn_material(&self, color: PieceColor) -> bool {
+        self.pieces_bb[color][PieceType::Rook]
+            | self.pieces_bb[color][PieceType::Bishop]
+            | self.pieces_bb[color][PieceType::Queen]
+            | self.pieces_bb[color][PieceType::Knight]
+            != 0
     }
 
     #[inline]
@@ -387,6 +443,387 @@ impl Board {
         .contains_bit(king_bit)
     }
 
+    /// bitboard of the enemy pieces that are giving check to the side to move
+    pub fn get_checkers(&self) -> u64 {
+        let (us_pieces_bb, enemy_pieces_bb, us_color_bb, enemy_color_bb) =
+            self.get_us_enemy_bitboards(self.color_to_move);
+        find_attackers(
+            us_pieces_bb[PieceType::King],
+            self.color_to_move,
+            enemy_pieces_bb,
+            us_color_bb | enemy_color_bb,
+        )
+    }
+
+    /// the board doesn't keep track of the fullmove counter, so it is estimated from the number of moves made
+    pub fn to_fen(&self) -> String {
+        let mut res = String::new();
+
+        for rank in (0..8).rev() {
+            let mut empty_squares = 0;
+            for file in 0..8 {
+                if let Some(piece) = self.get_piece_at(rank * 8 + file) {
+                    if empty_squares != 0 {
+                        res.push_str(&empty_squares.to_string());
+                        empty_squares = 0;
+                    }
+                    res.push_str(&piece.to_string());
+                } else {
+                    empty_squares += 1;
+                }
+            }
+
+            if empty_squares != 0 {
+                res.push_str(&empty_squares.to_string());
+            }
+            if rank != 0 {
+                res.push('/');
+            }
+        }
+
+        res.push_str(match self.color_to_move {
+            PieceColor::White => " w ",
+            PieceColor::Black => " b ",
+        });
+
+        let gamestate = self.current_gamestate;
+        let castling_rights: String = [
+            (gamestate.can_white_castle_kingside(), 'K'),
+            (gamestate.can_white_castle_queenside(), 'Q'),
+            (gamestate.can_black_castle_kingside(), 'k'),
+            (gamestate.can_black_castle_queenside(), 'q'),
+        ]
+        .iter()
+        .filter(|(can_castle, _)| *can_castle)
+        .map(|(_, c)| *c)
+        .collect();
+
+        if castling_rights.is_empty() {
+            res.push('-');
+        } else {
+            res.push_str(&castling_rights);
+        }
+
+        res.push(' ');
+        match gamestate.get_enpassant_square() {
+            Some(square) => res.push_str(&square_to_str(square)),
+            None => res.push('-'),
+        }
+
+        res.push_str(&format!(
+            " {} {}",
+            gamestate.halfmove_clock,
+            1 + self.gamestate_stack.len() / 2
+        ));
+
+        res
+    }
+
+    /// Checks that the redundant representations of the position agree with each other
+    /// and that the incrementally updated zobrist key matches the one computed from scratch
+    pub fn check_consistency(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        for color in [PieceColor::White, PieceColor::Black] {
+            let union = self.pieces_bb[color]
+                .iter()
+                .copied()
+                .fold(0u64, |acc, bb| acc | bb);
+            if union != self.colors_bb[color] {
+                errors.push(format!(
+                    "colors_bb[{:?}] is {:#018x} but its pieces_bb add up to {:#018x}",
+                    color, self.colors_bb[color], union
+                ));
+            }
+
+            for (i, bb) in self.pieces_bb[color].iter().enumerate() {
+                for other_bb in &self.pieces_bb[color][i + 1..] {
+                    if bb & other_bb != 0 {
+                        errors.push(format!(
+                            "{:?} {:?} overlaps another piece type on {:#018x}",
+                            color,
+                            PieceType::from(i),
+                            bb & other_bb
+                        ));
+                    }
+                }
+            }
+        }
+
+        if self.colors_bb[PieceColor::White] & self.colors_bb[PieceColor::Black] != 0 {
+            errors.push(format!(
+                "white and black overlap on {:#018x}",
+                self.colors_bb[PieceColor::White] & self.colors_bb[PieceColor::Black]
+            ));
+        }
+
+        for square in 0..64u64 {
+            let bb_piece = (0..2usize).find_map(|color| {
+                (0..6usize)
+                    .find(|&piece_type| self.pieces_bb[color][piece_type].contains_index(square))
+                    .map(|piece_type| {
+                        Piece::new(PieceType::from(piece_type), PieceColor::from(color))
+                    })
+            });
+
+            if bb_piece != self.get_piece_at(square) {
+                errors.push(format!(
+                    "on {} the bitboards have {:?} but piece_matrix has {:?}",
+                    square_to_str(square),
+                    bb_piece.map(|p| p.to_string()),
+                    self.get_piece_at(square).map(|p| p.to_string())
+                ));
+            }
+        }
+
+        let zobrist_from_scratch = zobrist::init_zobrist_key(self);
+        if zobrist_from_scratch != self.current_zobrist_key {
+            errors.push(format!(
+                "current_zobrist_key is {:#018x} but computing it from scratch gives {:#018x}",
+                self.current_zobrist_key, zobrist_from_scratch
+            ));
+        }
+
+        if self.current_gamestate.zobrist_key != self.current_zobrist_key {
+            errors.push(format!(
+                "current_gamestate.zobrist_key is {:#018x} but current_zobrist_key is {:#018x}",
+                self.current_gamestate.zobrist_key, self.current_zobrist_key
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    /// Everything `check_consistency` verifies plus the rules a reachable position has to respect:
+    /// one king per side, castling rights backed by king and rook on their squares, a plausible en passant
+    /// square and the side that just moved not being in check
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        if let Err(error) = self.check_consistency() {
+            errors.push(error);
+        }
+
+        for color in [PieceColor::White, PieceColor::Black] {
+            let num_kings = self.pieces_bb[color][PieceType::King].count_ones();
+            if num_kings != 1 {
+                errors.push(format!("{:?} has {} kings", color, num_kings));
+            }
+        }
+
+        // (right, king square, rook square, color, name)
+        let castling_requirements = [
+            (
+                self.current_gamestate.can_white_castle_kingside(),
+                4,
+                7,
+                PieceColor::White,
+                "K",
+            ),
+            (
+                self.current_gamestate.can_white_castle_queenside(),
+                4,
+                0,
+                PieceColor::White,
+                "Q",
+            ),
+            (
+                self.current_gamestate.can_black_castle_kingside(),
+                60,
+                63,
+                PieceColor::Black,
+                "k",
+            ),
+            (
+                self.current_gamestate.can_black_castle_queenside(),
+                60,
+                56,
+                PieceColor::Black,
+                "q",
+            ),
+        ];
+        for (can_castle, king_square, rook_square, color, name) in castling_requirements {
+            if can_castle
+                && (self.get_piece_at(king_square) != Some(Piece::new(PieceType::King, color))
+                    || self.get_piece_at(rook_square) != Some(Piece::new(PieceType::Rook, color)))
+            {
+                errors.push(format!(
+                    "castling right {} is set but king and rook are not on {} and {}",
+                    name,
+                    square_to_str(king_square),
+                    square_to_str(rook_square)
+                ));
+            }
+        }
+
+        if let Some(enpassant_square) = self.current_gamestate.get_enpassant_square() {
+            // the pawn that just double pushed passed over the en passant square
+            let (expected_rank, pawn_square, origin_square) = match self.color_to_move {
+                PieceColor::White => (5, enpassant_square - 8, enpassant_square + 8),
+                PieceColor::Black => (2, enpassant_square + 8, enpassant_square - 8),
+            };
+
+            if enpassant_square / 8 != expected_rank
+                || self.get_piece_at(enpassant_square).is_some()
+                || self.get_piece_at(origin_square).is_some()
+                || self.get_piece_at(pawn_square)
+                    != Some(Piece::new(PieceType::Pawn, !self.color_to_move))
+            {
+                errors.push(format!(
+                    "en passant square {} is not behind a pawn that just double pushed",
+                    square_to_str(enpassant_square)
+                ));
+            }
+        }
+
+        let (us_color_bb, enemy_color_bb) = self.get_us_enemy_colors_bb(self.color_to_move);
+        let enemy_king_bit = self.pieces_bb[!self.color_to_move][PieceType::King].isolate_ls1b();
+        let us_attacks = generate_attacks(
+            self.pieces_bb[self.color_to_move],
+            us_color_bb | enemy_color_bb,
+            self.color_to_move,
+        )
+        .iter()
+        .copied()
+        .fold(0u64, |acc, bb| acc | bb);
+        if us_attacks.contains_bit(enemy_king_bit) {
+            errors.push(format!(
+                "{:?} is to move but the {:?} king is in check",
+                self.color_to_move, !self.color_to_move
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    /// Panics with a full dump of the board if any invariant doesn't hold
+    #[cfg(feature = "board-invariants")]
+    pub fn assert_consistent(&self) {
+        if let Err(error) = self.check_invariants() {
+            eprintln!("{}", self.diagnostic_dump());
+            panic!("board invariants violated:\n{}", error);
+        }
+    }
+
+    #[cfg(feature = "board-invariants")]
+    fn diagnostic_dump(&self) -> String {
+        let mut res = String::from("---------------- board dump ----------------\n");
+
+        for rank in (0..8).rev() {
+            for file in 0..8 {
+                match self.get_piece_at(rank * 8 + file) {
+                    Some(piece) => res.push_str(&format!("{} ", piece)),
+                    None => res.push_str("- "),
+                }
+            }
+            res.push('\n');
+        }
+
+        res.push_str(&format!("fen: {}\n", self.to_fen()));
+        res.push_str(&format!("color to move: {:?}\n", self.color_to_move));
+
+        for color in [PieceColor::White, PieceColor::Black] {
+            res.push_str(&format!(
+                "colors_bb[{:?}]: {:#018x}\n",
+                color, self.colors_bb[color]
+            ));
+            for (piece_type, bb) in self.pieces_bb[color].iter().enumerate() {
+                res.push_str(&format!(
+                    "pieces_bb[{:?}][{:?}]: {:#018x}\n",
+                    color,
+                    PieceType::from(piece_type),
+                    bb
+                ));
+            }
+        }
+
+        res.push_str(&format!(
+            "current_zobrist_key: {:#018x}, from scratch: {:#018x}\n",
+            self.current_zobrist_key,
+            zobrist::init_zobrist_key(self)
+        ));
+        res.push_str(&format!(
+            "current_gamestate: {:?}\n",
+            self.current_gamestate
+        ));
+        res.push_str(&format!(
+            "gamestate_stack ({} entries, most recent first):\n",
+            self.gamestate_stack.len()
+        ));
+        for gamestate in self.gamestate_stack.iter().rev().take(8) {
+            res.push_str(&format!("  {:?}\n", gamestate));
+        }
+
+        res
+    }
+
+    /// Lists every field in which two boards differ, the cached legal moves are not compared
+    pub fn find_differences(&self, other: &Board) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        if self.pieces_bb != other.pieces_bb {
+            errors.push(format!(
+                "pieces_bb differ: {:?} vs {:?}",
+                self.pieces_bb, other.pieces_bb
+            ));
+        }
+        if self.colors_bb != other.colors_bb {
+            errors.push(format!(
+                "colors_bb differ: {:?} vs {:?}",
+                self.colors_bb, other.colors_bb
+            ));
+        }
+        if self.color_to_move != other.color_to_move {
+            errors.push(format!(
+                "color_to_move differs: {:?} vs {:?}",
+                self.color_to_move, other.color_to_move
+            ));
+        }
+        for square in 0..64u64 {
+            if self.get_piece_at(square) != other.get_piece_at(square) {
+                errors.push(format!(
+                    "piece_matrix differs on {}: {:?} vs {:?}",
+                    square_to_str(square),
+                    self.get_piece_at(square).map(|p| p.to_string()),
+                    other.get_piece_at(square).map(|p| p.to_string())
+                ));
+            }
+        }
+        if self.current_gamestate != other.current_gamestate {
+            errors.push(format!(
+                "current_gamestate differs: {:?} vs {:?}",
+                self.current_gamestate, other.current_gamestate
+            ));
+        }
+        if self.gamestate_stack != other.gamestate_stack {
+            errors.push(format!(
+                "gamestate_stack differs (lengths {} vs {})",
+                self.gamestate_stack.len(),
+                other.gamestate_stack.len()
+            ));
+        }
+        if self.current_zobrist_key != other.current_zobrist_key {
+            errors.push(format!(
+                "current_zobrist_key differs: {:#018x} vs {:#018x}",
+                self.current_zobrist_key, other.current_zobrist_key
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
     pub fn _print_matrix(&self) {
         for row in (0..8).rev() {
             for col in 0..8 {
@@ -396,7 +833,21 @@ impl Board {
                     print!("- ");
                 }
             }
-            println!("");
+            println!();
         }
     }
 }
+
+pub fn square_to_str(index: u64) -> String {
+    let y = index / 8;
+    let x = index % 8;
+
+    ((b'a' + x as u8) as char).to_string() + &((y as u8 + b'1') as char).to_string()
+}
+
+pub fn str_to_square(name: &str) -> u64 {
+    let mut chars = name.chars();
+
+    ((chars.next().unwrap() as u32 - 'a' as u32)
+        + (chars.next().unwrap().to_digit(10).unwrap() - 1) * 8) as u64
+}
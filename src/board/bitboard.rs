@@ -54,6 +54,7 @@ impl Bitmanip for u64 {
     }
 }
 
+#[allow(dead_code)]
 pub fn print_bitboard(bitboard: u64) {
     println!();
     const LAST_BIT: u64 = 63;
@@ -1,8 +1,8 @@
-use crate::search::perft::str_to_square;
+use super::str_to_square;
 
 use super::{Piece, PieceColor};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Gamestate {
     pub last_piece_captured: Option<Piece>,
     pub enpassant_square: Option<u64>,
@@ -134,7 +134,7 @@ impl Not for PieceColor {
 
 // ---------------------------------------------------------------------------------------------------
 
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Piece {
     piece_code: u8,
 }
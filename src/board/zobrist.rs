@@ -882,4 +882,19 @@ pub fn init_zobrist_key(board: &Board) -> u64 {
     res ^= castling(board.current_gamestate.get_castling_rights());
     
     res
-}
\ No newline at end of file
+}
+
+/// fingerprint of all the keys, to recognize data hashed with other keys, like a saved transposition table
+pub fn keys_checksum() -> u64 {
+    ZOBRISTKEYS
+        .pieces
+        .iter()
+        .flatten()
+        .flatten()
+        .chain(ZOBRISTKEYS.black_is_moving.iter())
+        .chain(ZOBRISTKEYS.castling_rights.iter())
+        .chain(ZOBRISTKEYS.enpassant_files.iter())
+        .fold(0u64, |acc, &key| {
+            (acc.rotate_left(5) ^ key).wrapping_mul(0x9E3779B97F4A7C15)
+        })
+}
@@ -1,6 +1,7 @@
 mod board;
 mod consts;
 mod move_gen;
+mod rng;
 mod search;
 
 use std::{
@@ -15,39 +16,89 @@ use std::{
 
 use crate::board::Board;
 
-use board::{bitboard::print_bitboard, piece::*};
+use board::piece::*;
 use move_gen::chess_move::Move;
 use move_gen::generate_legal_moves;
-use search::perft::perft_test;
+use search::{
+    SearchOptions,
+    perft::{hashed_perft_test, perft_stats_test, perft_test},
+    selfcheck::random_games_check,
+    skill::{MAX_ELO, MAX_SKILL_LEVEL, MIN_ELO},
+    skill_match::play_skill_match,
+    time_manager::SearchLimits,
+    tt::TranspositionTable,
+};
 
 const _INITIAL_FEN_STRING: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"; //KQkq -";
 const _TEST_FEN_STRING: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - ";
 
+const MAX_THREADS: usize = 256;
+const DEFAULT_HASH_MB: usize = 64;
+const MAX_HASH_MB: usize = 65536;
+const MAX_MOVE_OVERHEAD_MS: u64 = 5000;
+const MAX_MULTI_PV: usize = 256;
+// the moves listed after searchmoves end at the next of these
+const GO_KEYWORDS: [&str; 12] = [
+    "searchmoves",
+    "ponder",
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "depth",
+    "nodes",
+    "mate",
+    "movetime",
+    "infinite",
+];
+
 fn main() {
     let mut board = Board::new(_INITIAL_FEN_STRING);
+    let mut search_options = SearchOptions::default();
+    let mut tt = TranspositionTable::new(DEFAULT_HASH_MB);
 
     let (tx, rx) = mpsc::channel();
     let stop_flag = Arc::new(AtomicBool::from(false));
+    let ponder_flag = Arc::new(AtomicBool::from(false));
+    let searching_flag = Arc::new(AtomicBool::from(false));
     let stop_clone = Arc::clone(&stop_flag);
-    let _ = thread::spawn(move || handle_stdin(tx, stop_clone));
+    let ponder_clone = Arc::clone(&ponder_flag);
+    let searching_clone = Arc::clone(&searching_flag);
+    let _ = thread::spawn(move || handle_stdin(tx, stop_clone, ponder_clone, searching_clone));
 
     loop {
-        stop_flag.store(false, Ordering::SeqCst);
         let uci_command = rx.recv().unwrap();
         match uci_command[0].as_str() {
-            "ucinewgame" => board = Board::new(_INITIAL_FEN_STRING),
+            "ucinewgame" => {
+                board = Board::new(_INITIAL_FEN_STRING);
+                tt.clear();
+            }
             "position" => board = handle_position(uci_command[1..].to_vec()),
-            "perft" => {
-                let depth = uci_command[1].parse::<u8>().unwrap();
-                perft_test(depth, &mut board, &stop_flag);
+            "setoption" => {
+                handle_setoption(uci_command[1..].to_vec(), &mut search_options, &mut tt)
             }
+            "perft" => handle_perft(uci_command[1..].to_vec(), &mut board, &stop_flag),
+            "selfcheck" => handle_selfcheck(uci_command[1..].to_vec(), &stop_flag),
+            "skillmatch" => handle_skillmatch(uci_command[1..].to_vec(), &stop_flag),
+            "savehash" => handle_savehash(uci_command[1..].to_vec(), &tt),
+            "loadhash" => handle_loadhash(uci_command[1..].to_vec(), &mut tt),
             "captures" => {
                 let captures = generate_legal_moves(&board, false);
                 println!("captures: {}", captures.len());
             }
             "go" => {
-                handle_go(uci_command[1..].to_vec(), &mut board, &stop_flag);
+                handle_go(
+                    uci_command[1..].to_vec(),
+                    &mut board,
+                    &mut tt,
+                    &stop_flag,
+                    &ponder_flag,
+                    &search_options,
+                );
+                searching_flag.store(false, Ordering::SeqCst);
             }
+            "debug" => search_options.debug = uci_command.get(1).is_some_and(|arg| arg == "on"),
             "isready" => println!("readyok"),
             "quit" => return,
             _ => {}
@@ -55,31 +106,257 @@ fn main() {
     }
 }
 
-fn handle_go(command: Vec<String>, board: &mut Board, stop_flag: &Arc<AtomicBool>) {
-    let mut wtime = 20u64;
-    let mut btime = 20u64;
-    let mut winc = 0u64;
-    let mut binc = 0u64;
+fn handle_go(
+    command: Vec<String>,
+    board: &mut Board,
+    tt: &mut TranspositionTable,
+    stop_flag: &Arc<AtomicBool>,
+    ponder_flag: &AtomicBool,
+    search_options: &SearchOptions,
+) {
+    let mut limits = SearchLimits::default();
 
-    let mut tokens = command.into_iter();
+    let mut tokens = command.into_iter().peekable();
 
     while let Some(token) = tokens.next() {
         match token.as_str() {
-            "wtime" => wtime = tokens.next().unwrap().parse::<u64>().unwrap(),
-            "btime" => btime = tokens.next().unwrap().parse::<u64>().unwrap(),
-            "winc" => winc = tokens.next().unwrap().parse::<u64>().unwrap(),
-            "binc" => binc = tokens.next().unwrap().parse::<u64>().unwrap(),
+            "searchmoves" => {
+                let legal_moves = generate_legal_moves(board, true);
+                let mut any_listed = false;
+
+                while let Some(token) =
+                    tokens.next_if(|token| !GO_KEYWORDS.contains(&token.as_str()))
+                {
+                    any_listed = true;
+                    match legal_moves
+                        .iter()
+                        .find(|m| m.to_long_algebraic_notation() == token)
+                    {
+                        Some(m) if limits.search_moves.contains(&m) => {}
+                        Some(m) => limits.search_moves.push(m),
+                        None => println!("info string ignoring illegal searchmove {}", token),
+                    }
+                }
+
+                if any_listed && limits.search_moves.is_empty() {
+                    println!("info string no legal searchmove, searching every move");
+                }
+            }
+            "wtime" => limits.time[PieceColor::White] = Some(parse_ms(tokens.next())),
+            "btime" => limits.time[PieceColor::Black] = Some(parse_ms(tokens.next())),
+            "winc" => limits.increment[PieceColor::White] = parse_ms(tokens.next()),
+            "binc" => limits.increment[PieceColor::Black] = parse_ms(tokens.next()),
+            "movestogo" => {
+                limits.moves_to_go = Some(tokens.next().unwrap().parse::<u64>().unwrap())
+            }
+            "movetime" => limits.move_time = Some(parse_ms(tokens.next())),
+            "depth" => limits.depth = Some(tokens.next().unwrap().parse::<u8>().unwrap()),
+            "nodes" => limits.nodes = Some(tokens.next().unwrap().parse::<u64>().unwrap()),
+            "mate" => limits.mate = Some(tokens.next().unwrap().parse::<u32>().unwrap()),
+            "infinite" => limits.infinite = true,
+            "ponder" => limits.ponder = true,
             _ => {}
         }
     }
 
-    let think_time: u64 = if board.get_color_to_move() == PieceColor::White {
-        wtime / 20 + winc / 2
+    search::iterative_deepening_search(board, tt, &limits, stop_flag, ponder_flag, search_options);
+}
+
+/// times in milliseconds, some guis send negative ones when the clock already ran out
+fn parse_ms(token: Option<String>) -> u64 {
+    token.unwrap().parse::<i64>().unwrap().max(0) as u64
+}
+
+/// setoption name <name> [value <value>], option names are case insensitive and may contain spaces
+fn handle_setoption(
+    command: Vec<String>,
+    search_options: &mut SearchOptions,
+    tt: &mut TranspositionTable,
+) {
+    let value_index = command
+        .iter()
+        .position(|token| token == "value")
+        .unwrap_or(command.len());
+    let name = command[1.min(value_index)..value_index].join(" ");
+    let value = command[(value_index + 1).min(command.len())..].join(" ");
+
+    if name.eq_ignore_ascii_case("Threads") {
+        match value.parse::<usize>() {
+            Ok(threads) => search_options.threads = threads.clamp(1, MAX_THREADS),
+            Err(_) => println!("info string invalid value for Threads: {}", value),
+        }
+    } else if name.eq_ignore_ascii_case("Hash") {
+        match value.parse::<usize>() {
+            Ok(size_mb) => tt.resize(size_mb.clamp(1, MAX_HASH_MB)),
+            Err(_) => println!("info string invalid value for Hash: {}", value),
+        }
+    } else if name.eq_ignore_ascii_case("Move Overhead") {
+        match value.parse::<u64>() {
+            Ok(overhead_ms) => {
+                search_options.move_overhead_ms = overhead_ms.min(MAX_MOVE_OVERHEAD_MS)
+            }
+            Err(_) => println!("info string invalid value for Move Overhead: {}", value),
+        }
+    } else if name.eq_ignore_ascii_case("MultiPV") {
+        match value.parse::<usize>() {
+            Ok(multi_pv) => search_options.multi_pv = multi_pv.clamp(1, MAX_MULTI_PV),
+            Err(_) => println!("info string invalid value for MultiPV: {}", value),
+        }
+    } else if name.eq_ignore_ascii_case("Skill Level") {
+        match value.parse::<u8>() {
+            Ok(level) => search_options.skill_level = level.min(MAX_SKILL_LEVEL),
+            Err(_) => println!("info string invalid value for Skill Level: {}", value),
+        }
+    } else if name.eq_ignore_ascii_case("UCI_LimitStrength") {
+        match value.to_ascii_lowercase().parse::<bool>() {
+            Ok(limit_strength) => search_options.limit_strength = limit_strength,
+            Err(_) => println!("info string invalid value for UCI_LimitStrength: {}", value),
+        }
+    } else if name.eq_ignore_ascii_case("UCI_Elo") {
+        match value.parse::<u32>() {
+            Ok(elo) => search_options.elo = elo.clamp(MIN_ELO, MAX_ELO),
+            Err(_) => println!("info string invalid value for UCI_Elo: {}", value),
+        }
+    } else if name.eq_ignore_ascii_case("Ponder") {
+        match value.to_ascii_lowercase().parse::<bool>() {
+            Ok(ponder) => search_options.ponder = ponder,
+            Err(_) => println!("info string invalid value for Ponder: {}", value),
+        }
+    } else if name.eq_ignore_ascii_case("Clear Hash") {
+        tt.clear();
     } else {
-        btime / 20 + binc / 2
-    };
+        println!("info string unknown option: {}", name);
+    }
+}
+
+/// perft <depth> [threads <n>] [hash <mb>] [stats]
+/// the plain recursion is used unless threads or hash are given, stats prints the per-category breakdown
+fn handle_perft(command: Vec<String>, board: &mut Board, stop_flag: &Arc<AtomicBool>) {
+    let depth = command[0].parse::<u8>().unwrap();
+    let mut num_threads: Option<usize> = None;
+    let mut hash_size_mb: Option<usize> = None;
+    let mut print_stats = false;
+
+    let mut tokens = command.into_iter().skip(1);
+
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "threads" => num_threads = Some(tokens.next().unwrap().parse::<usize>().unwrap()),
+            "hash" => hash_size_mb = Some(tokens.next().unwrap().parse::<usize>().unwrap()),
+            "stats" => print_stats = true,
+            _ => {}
+        }
+    }
+
+    if print_stats {
+        if num_threads.is_some() || hash_size_mb.is_some() {
+            println!(
+                "info string perft stats only runs the plain recursion, threads and hash are ignored"
+            );
+        }
+        perft_stats_test(depth, board, stop_flag);
+        return;
+    }
 
-    search::iterative_deepening_search(board, think_time, stop_flag);
+    if num_threads.is_none() && hash_size_mb.is_none() {
+        perft_test(depth, board, stop_flag);
+        return;
+    }
+
+    let num_threads =
+        num_threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    hashed_perft_test(
+        depth,
+        board,
+        num_threads,
+        hash_size_mb.unwrap_or(256),
+        stop_flag,
+    );
+}
+
+/// selfcheck [games <n>] [plies <n>] [seed <n>]
+fn handle_selfcheck(command: Vec<String>, stop_flag: &Arc<AtomicBool>) {
+    let mut num_games = 1000u32;
+    let mut max_plies = 300u32;
+    let mut seed = rng::Rng::from_time().next_u64();
+
+    let mut tokens = command.into_iter();
+
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "games" => num_games = tokens.next().unwrap().parse::<u32>().unwrap(),
+            "plies" => max_plies = tokens.next().unwrap().parse::<u32>().unwrap(),
+            "seed" => seed = tokens.next().unwrap().parse::<u64>().unwrap(),
+            _ => {}
+        }
+    }
+
+    random_games_check(num_games, max_plies, seed, stop_flag);
+}
+
+/// skillmatch <level> [against <level>] [games <n>] [movetime <ms>]
+fn handle_skillmatch(command: Vec<String>, stop_flag: &Arc<AtomicBool>) {
+    let skill_level = command[0].parse::<u8>().unwrap().min(MAX_SKILL_LEVEL);
+    let mut opponent_level = MAX_SKILL_LEVEL;
+    let mut num_games = 20u32;
+    let mut move_time_ms = 100u64;
+
+    let mut tokens = command.into_iter().skip(1);
+
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "against" => {
+                opponent_level = tokens
+                    .next()
+                    .unwrap()
+                    .parse::<u8>()
+                    .unwrap()
+                    .min(MAX_SKILL_LEVEL)
+            }
+            "games" => num_games = tokens.next().unwrap().parse::<u32>().unwrap(),
+            "movetime" => move_time_ms = parse_ms(tokens.next()),
+            _ => {}
+        }
+    }
+
+    play_skill_match(
+        skill_level,
+        opponent_level,
+        num_games,
+        move_time_ms,
+        stop_flag,
+    );
+}
+
+/// savehash <file>
+fn handle_savehash(command: Vec<String>, tt: &TranspositionTable) {
+    let path = command.join(" ");
+
+    match tt.save(&path) {
+        Ok(()) => println!("info string hash saved to {}", path),
+        Err(error) => println!("info string {}", error),
+    }
+}
+
+/// loadhash <file>, the table takes the size it was saved with
+fn handle_loadhash(command: Vec<String>, tt: &mut TranspositionTable) {
+    let path = command.join(" ");
+
+    match TranspositionTable::load(&path) {
+        Ok(loaded) => {
+            if loaded.size_mb() != tt.size_mb() {
+                println!(
+                    "info string {} holds a {} MB table, which replaces the {} MB one of the Hash option",
+                    path,
+                    loaded.size_mb(),
+                    tt.size_mb()
+                );
+            }
+            *tt = loaded;
+            println!("info string hash loaded from {}", path);
+        }
+        Err(error) => println!("info string {}", error),
+    }
 }
 
 fn handle_position(command: Vec<String>) -> Board {
@@ -131,17 +408,83 @@ fn handle_position(command: Vec<String>) -> Board {
     res
 }
 
-fn handle_stdin(tx: mpsc::Sender<Vec<String>>, stop_flag: Arc<AtomicBool>) {
+fn handle_stdin(
+    tx: mpsc::Sender<Vec<String>>,
+    stop_flag: Arc<AtomicBool>,
+    ponder_flag: Arc<AtomicBool>,
+    searching_flag: Arc<AtomicBool>,
+) {
+    // quit only stops a go, so that a scripted perft or selfcheck still runs to the end before exiting
+    let stop_search = || {
+        if searching_flag.load(Ordering::SeqCst) {
+            stop_flag.store(true, Ordering::SeqCst);
+        }
+    };
+
     loop {
         let mut line = String::new();
-        let _ = io::stdin().read_line(&mut line);
+        // the end of the input is a quit, a gui that closed the pipe will never send one
+        if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            stop_search();
+            let _ = tx.send(vec!["quit".to_string()]);
+            return;
+        }
         let splits: Vec<String> = line.split_whitespace().map(|s| s.to_string()).collect();
+        if splits.is_empty() {
+            continue;
+        }
 
         match splits[0].as_str() {
             "stop" => stop_flag.store(true, Ordering::SeqCst),
+            // set here rather than by the search, so that a ponderhit or a stop sent right after the go isn't
+            // missed while an earlier command is still running
+            "go" => {
+                stop_flag.store(false, Ordering::SeqCst);
+                ponder_flag.store(splits.iter().any(|s| s == "ponder"), Ordering::SeqCst);
+                searching_flag.store(true, Ordering::SeqCst);
+                tx.send(splits).unwrap();
+            }
+            "perft" | "selfcheck" | "skillmatch" => {
+                stop_flag.store(false, Ordering::SeqCst);
+                tx.send(splits).unwrap();
+            }
+            "ponderhit" => ponder_flag.store(false, Ordering::SeqCst),
+            // a running search is stopped first, so that quit is read as soon as it ends
+            "quit" => {
+                stop_search();
+                tx.send(splits).unwrap();
+            }
             "uci" => {
                 println!("id name {}", env!("CARGO_PKG_NAME"));
                 println!("id author Rick");
+                println!(
+                    "option name Threads type spin default 1 min 1 max {}",
+                    MAX_THREADS
+                );
+                println!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_HASH_MB, MAX_HASH_MB
+                );
+                println!("option name Clear Hash type button");
+                println!("option name Ponder type check default false");
+                println!(
+                    "option name MultiPV type spin default 1 min 1 max {}",
+                    MAX_MULTI_PV
+                );
+                println!(
+                    "option name Skill Level type spin default {} min 0 max {}",
+                    MAX_SKILL_LEVEL, MAX_SKILL_LEVEL
+                );
+                println!("option name UCI_LimitStrength type check default false");
+                println!(
+                    "option name UCI_Elo type spin default {} min {} max {}",
+                    MAX_ELO, MIN_ELO, MAX_ELO
+                );
+                println!(
+                    "option name Move Overhead type spin default {} min 0 max {}",
+                    SearchOptions::default().move_overhead_ms,
+                    MAX_MOVE_OVERHEAD_MS
+                );
                 println!("uciok");
             }
             _ => tx.send(splits).unwrap(),
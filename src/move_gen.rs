@@ -2,12 +2,7 @@ pub mod chess_move;
 pub mod move_list;
 
 use crate::{
-    board::{
-        Board,
-        bitboard::{Bitmanip, print_bitboard},
-        gamestate::Gamestate,
-        piece::*,
-    },
+    board::{Board, bitboard::Bitmanip, gamestate::Gamestate, piece::*},
     consts::CONSTS,
 };
 
@@ -218,7 +213,7 @@ fn generate_pawn_attacks(pawn_bb: u64, piece_color: PieceColor) -> u64 {
     east_attacks | west_attacks
 }
 
-fn find_attackers(
+pub fn find_attackers(
     king_bb: u64,
     king_color: PieceColor,
     enemy_pieces_bb: [u64; 6],
@@ -245,6 +240,46 @@ fn find_attackers(
     attackers
 }
 
+/// bitboard of the pieces of both colors attacking the square, with sliders blocked by occupied_bb
+pub fn attackers_to(square_bb: u64, pieces_bb: [[u64; 6]; 2], occupied_bb: u64) -> u64 {
+    let [white_pieces_bb, black_pieces_bb] = pieces_bb;
+    let pieces_of_type =
+        |piece_type: PieceType| white_pieces_bb[piece_type] | black_pieces_bb[piece_type];
+    let queens_bb = pieces_of_type(PieceType::Queen);
+
+    (generate_knight_attacks(square_bb) & pieces_of_type(PieceType::Knight))
+        | (generate_king_attacks(square_bb) & pieces_of_type(PieceType::King))
+        | (generate_bishop_attacks(square_bb, occupied_bb)
+            & (pieces_of_type(PieceType::Bishop) | queens_bb))
+        | (generate_rook_attacks(square_bb, occupied_bb)
+            & (pieces_of_type(PieceType::Rook) | queens_bb))
+        | (generate_pawn_attacks(square_bb, PieceColor::White) & black_pieces_bb[PieceType::Pawn])
+        | (generate_pawn_attacks(square_bb, PieceColor::Black) & white_pieces_bb[PieceType::Pawn])
+}
+
+/// pieces of the color pinned to their king, and the enemy sliders pinning them
+pub fn pins(board: &Board, color: PieceColor) -> (u64, u64) {
+    let (us_pieces_bb, enemy_pieces_bb, us_color_bb, enemy_color_bb) =
+        board.get_us_enemy_bitboards(color);
+    let occupied_bb = us_color_bb | enemy_color_bb;
+    let king_bit = us_pieces_bb[PieceType::King];
+
+    let pinners_bb = (xray_rook_attacks(occupied_bb, us_color_bb, king_bit)
+        & (enemy_pieces_bb[PieceType::Rook] | enemy_pieces_bb[PieceType::Queen]))
+        | (xray_bishop_attacks(occupied_bb, us_color_bb, king_bit)
+            & (enemy_pieces_bb[PieceType::Bishop] | enemy_pieces_bb[PieceType::Queen]));
+
+    let mut pinned_bb = 0;
+    let mut pinner = pinners_bb;
+    while pinner != 0 {
+        let index = pinner.bitscan_reset();
+        pinned_bb |=
+            CONSTS::SQUARES_BETWEEN[index as usize][king_bit.bitscan() as usize] & us_color_bb;
+    }
+
+    (pinned_bb, pinners_bb)
+}
+
 fn xray_rook_attacks(occupied_bb: u64, mut blockers_bb: u64, rook_bit: u64) -> u64 {
     let attacks = generate_rook_attacks(rook_bit, occupied_bb);
     blockers_bb &= attacks;
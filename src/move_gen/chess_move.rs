@@ -113,6 +113,18 @@ impl From<&str> for Move {
     }
 }
 
+impl From<u16> for Move {
+    fn from(move_code: u16) -> Self {
+        Move { move_code }
+    }
+}
+
+impl From<Move> for u16 {
+    fn from(value: Move) -> Self {
+        value.move_code
+    }
+}
+
 impl PartialEq for Move {
     fn eq(&self, other: &Self) -> bool {
         (self.move_code & 8191) == (other.move_code & 8191)
@@ -0,0 +1,39 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// xorshift64* generator, good enough for picking random moves and doesn't need any dependency
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // the state must never be zero or the generator gets stuck
+        let state = seed ^ 0x9E3779B97F4A7C15;
+        Rng {
+            state: if state == 0 {
+                0x9E3779B97F4A7C15
+            } else {
+                state
+            },
+        }
+    }
+
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Rng::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// random number in 0..bound
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
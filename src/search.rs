@@ -1,263 +1,1481 @@
 mod eval;
+mod history;
 pub mod perft;
+mod pv;
+mod see;
+pub mod selfcheck;
+pub mod skill;
+pub mod skill_match;
+mod stats;
+pub mod time_manager;
 pub mod tt;
 
 use std::{
+    cmp::Reverse,
     sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
     },
+    thread,
     time::{Duration, Instant},
 };
 
+use history::{MAX_HISTORY, PreviousMoves, SearchHistory};
+use pv::{PvTable, pv_to_string};
+use see::SEE_VALUES;
+use skill::{MAX_ELO, MAX_SKILL_LEVEL, Skill};
+use stats::PruningStats;
+use time_manager::{SearchLimits, TimeManager};
 use tt::{EntryType, TranspositionTable};
 
 use crate::{
-    board::Board,
-    move_gen::{chess_move::Move, generate_legal_moves},
+    board::{
+        Board,
+        piece::{Piece, PieceType},
+    },
+    move_gen::{chess_move::Move, generate_legal_moves, move_list::MoveList},
+    rng::Rng,
 };
 
-const MATE_THRESHOLD: i32 = i32::MAX - 50;
+pub const MAX_PLY: usize = 128;
+
+const INFINITY: i32 = 32000;
+// being mated at ply n scores -MATE_SCORE + n, so shorter mates are preferred
+const MATE_SCORE: i32 = 31000;
+// any score beyond this is a mate score
+const MATE_THRESHOLD: i32 = MATE_SCORE - MAX_PLY as i32;
+// deepest iteration, when go doesn't give a depth
+const MAX_DEPTH: u8 = 100;
+
+// window used around the score of the previous iteration, widened by half its size on every fail
+const ASPIRATION_DELTA: i32 = 25;
+const ASPIRATION_MIN_DEPTH: u8 = 4;
+
+// reverse futility pruning: at shallow depth a node whose static eval beats beta by
+// RFP_MARGIN per ply of depth is assumed to fail high
+const RFP_MAX_DEPTH: u8 = 6;
+const RFP_MARGIN: i32 = 80;
+
+// razoring: at shallow depth a node whose static eval is RAZOR_MARGIN per ply of depth below alpha
+// is resolved by a quiescence search, unless that finds a way back above alpha
+const RAZOR_MAX_DEPTH: u8 = 3;
+const RAZOR_MARGIN: i32 = 250;
+
+// futility pruning: quiet moves are skipped when the static eval plus
+// FUTILITY_BASE_MARGIN + FUTILITY_MARGIN per ply of depth can't reach alpha
+const FUTILITY_MAX_DEPTH: u8 = 6;
+const FUTILITY_BASE_MARGIN: i32 = 100;
+const FUTILITY_MARGIN: i32 = 90;
+
+// late move pruning: at shallow depth only the first LMP_BASE_MOVES + depth * depth moves may be quiet
+const LMP_MAX_DEPTH: u8 = 6;
+const LMP_BASE_MOVES: usize = 3;
+
+// delta pruning: captures in quiescence are skipped when the static eval plus the captured piece
+// plus DELTA_MARGIN can't reach alpha
+const DELTA_MARGIN: i32 = 200;
+
+// singular extensions: from SE_MIN_DEPTH a tt move whose lower bound is at least SE_MAX_TT_DEPTH_GAP
+// plies deep is extended when the other moves, searched at half depth, all fail low against the tt score
+// lowered by SE_MARGIN per ply of depth
+const SE_MIN_DEPTH: u8 = 8;
+const SE_MAX_TT_DEPTH_GAP: u8 = 3;
+const SE_MARGIN: i32 = 2;
+
+// null move pruning: R = NMP_BASE_REDUCTION + depth / NMP_DEPTH_DIVISOR, and from NMP_VERIFICATION_DEPTH
+// on a fail high is only trusted after a normal search without null moves confirms it (zugzwang)
+const NMP_MIN_DEPTH: u8 = 3;
+const NMP_BASE_REDUCTION: u8 = 3;
+const NMP_DEPTH_DIVISOR: u8 = 4;
+const NMP_VERIFICATION_DEPTH: u8 = 8;
+
+// late move reductions are applied from this depth, to the moves after the first LMR_MIN_MOVE_INDEX ones
+const LMR_MIN_DEPTH: u8 = 3;
+const LMR_MIN_MOVE_INDEX: usize = 2;
+// quiet moves with a good history are reduced less, one ply less every LMR_HISTORY_DIVISOR points
+const LMR_HISTORY_DIVISOR: i32 = MAX_HISTORY;
+
+// base reduction indexed by [depth][move index]
+static LMR_REDUCTIONS: LazyLock<[[i32; 64]; 64]> = LazyLock::new(|| {
+    let mut table = [[0; 64]; 64];
+    for (depth, row) in table.iter_mut().enumerate().skip(1) {
+        for (move_index, reduction) in row.iter_mut().enumerate().skip(1) {
+            *reduction = (0.75 + (depth as f64).ln() * (move_index as f64).ln() / 2.25) as i32;
+        }
+    }
+    table
+});
+
+// root moves are reported with info currmove only once the search has been running for a while
+const CURRMOVE_REPORT_DELAY: Duration = Duration::from_millis(3000);
+
+// helper thread i skips the depths d where ((d + SKIP_PHASE[i]) / SKIP_SIZE[i]) is odd
+const SKIP_SIZE: [u8; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [u8; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+// the search recursion needs more than the default stack of spawned threads
+const SEARCH_THREAD_STACK_SIZE: usize = 16 * 1024 * 1024;
+
+/// What is known about the move made at a given ply of the current path
+#[derive(Clone, Copy, Default)]
+struct StackEntry {
+    null_move: bool,
+    current_move: Option<Move>,
+    moved_piece: Option<Piece>,
+    capture: bool,
+    // extensions made on the path from the root to this ply
+    extensions: u8,
+    // move skipped by the singular extension search of the tt move
+    excluded_move: Option<Move>,
+}
+
+/// Engine settings changed with setoption
+pub struct SearchOptions {
+    pub threads: usize,
+    // the gui lets the engine think on the opponent's time, so a bit more of our own can be used
+    pub ponder: bool,
+    // number of best root moves searched and reported
+    pub multi_pv: usize,
+    // Skill Level, or UCI_Elo when UCI_LimitStrength is set, weakens the play below the maximum
+    pub skill_level: u8,
+    pub limit_strength: bool,
+    pub elo: u32,
+    // time lost on every move by the gui and the communication, kept off the clock
+    pub move_overhead_ms: u64,
+    // set with the debug command, prints the search statistics after every search
+    pub debug: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            threads: 1,
+            ponder: false,
+            multi_pv: 1,
+            skill_level: MAX_SKILL_LEVEL,
+            limit_strength: false,
+            elo: MAX_ELO,
+            move_overhead_ms: 10,
+            debug: false,
+        }
+    }
+}
+
+/// Last completed iteration of a search thread, the move, score, depth and pv all come from it
+struct ThreadResult {
+    depth: u8,
+    score: i32,
+    best_move: Move,
+    pv: Vec<Move>,
+    // every MultiPV line of the last completed iteration, best first
+    lines: Vec<RootLine>,
+}
+
+/// One MultiPV line, the pv starts with its root move
+struct RootLine {
+    score: i32,
+    pv: Vec<Move>,
+}
+
+/// State shared by all the nodes searched by one thread
+struct SearchContext<'a> {
+    tt: &'a TranspositionTable,
+    now: Instant,
+    // only the main thread has limits, the helpers are stopped by it
+    time_limit: Option<Duration>,
+    node_limit: Option<u64>,
+    // the time limit counts from here, which is moved to the ponderhit when pondering
+    time_start: Instant,
+    // set for the main thread while it ponders, the limits only apply once the flag is cleared by ponderhit
+    ponder_flag: Option<&'a AtomicBool>,
+    stop_flag: &'a Arc<AtomicBool>,
+    // set once should_stop fired, the scores of the nodes being searched are then meaningless and are
+    // neither stored nor used for the move ordering
+    stopped: bool,
+    thread_id: usize,
+    // nodes searched by each thread, published by the threads for the info lines of the main one
+    thread_nodes: &'a [AtomicU64],
+    pv_table: Box<PvTable>,
+    history: Box<SearchHistory>,
+    nodes: u64,
+    qnodes: u64,
+    seldepth: usize,
+    stack: [StackEntry; MAX_PLY + 1],
+    // depth of the current iteration, a path may be extended by at most half of it
+    root_depth: u8,
+    // null moves are disabled before this ply while a null move fail high is being verified
+    nmp_min_ply: usize,
+    pruning_stats: PruningStats,
+}
+
+impl<'a> SearchContext<'a> {
+    fn new(
+        tt: &'a TranspositionTable,
+        now: Instant,
+        time_limit: Option<Duration>,
+        node_limit: Option<u64>,
+        stop_flag: &'a Arc<AtomicBool>,
+        thread_nodes: &'a [AtomicU64],
+        thread_id: usize,
+    ) -> Self {
+        SearchContext {
+            tt,
+            now,
+            time_limit,
+            node_limit,
+            time_start: now,
+            ponder_flag: None,
+            stop_flag,
+            stopped: false,
+            thread_id,
+            thread_nodes,
+            pv_table: Box::new(PvTable::new()),
+            history: Box::new(SearchHistory::new()),
+            nodes: 0,
+            qnodes: 0,
+            seldepth: 0,
+            stack: [StackEntry::default(); MAX_PLY + 1],
+            root_depth: 0,
+            nmp_min_ply: 0,
+            pruning_stats: PruningStats::default(),
+        }
+    }
+
+    #[inline]
+    fn should_stop(&mut self) -> bool {
+        self.thread_nodes[self.thread_id].store(self.nodes, Ordering::Relaxed);
+
+        // the first iteration always completes, so that the move played has been searched
+        if self.root_depth <= 1 {
+            return false;
+        }
+
+        self.stopped = self.stopped || self.limit_reached();
+        self.stopped
+    }
+
+    fn limit_reached(&mut self) -> bool {
+        if let Some(ponder_flag) = self.ponder_flag {
+            if ponder_flag.load(Ordering::SeqCst) {
+                return self.stop_flag.load(Ordering::SeqCst);
+            }
+            // ponderhit: the opponent played the expected move and our clock is running
+            self.ponder_flag = None;
+            self.time_start = Instant::now();
+        }
 
+        self.time_limit
+            .is_some_and(|time_limit| self.time_start.elapsed() >= time_limit)
+            || self
+                .node_limit
+                .is_some_and(|node_limit| self.total_nodes() >= node_limit)
+            || self.stop_flag.load(Ordering::SeqCst)
+    }
+
+    fn total_nodes(&self) -> u64 {
+        self.thread_nodes
+            .iter()
+            .map(|nodes| nodes.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// records the move about to be made at this ply, must be called before make_move
+    #[inline]
+    fn push_move(&mut self, board: &Board, ply: usize, m: Move) {
+        let entry = &mut self.stack[ply];
+        entry.null_move = false;
+        entry.current_move = Some(m);
+        entry.moved_piece = board.get_piece_at(m.get_from());
+        entry.capture = m.is_enpassant() || board.get_piece_at(m.get_to()).is_some();
+    }
+
+    #[inline]
+    fn push_null_move(&mut self, ply: usize) {
+        let entry = &mut self.stack[ply];
+        entry.null_move = true;
+        entry.current_move = None;
+        entry.moved_piece = None;
+        entry.capture = false;
+        self.stack[ply + 1].extensions = self.stack[ply].extensions;
+    }
+
+    /// the moves that led to the node at this ply, for the countermove and continuation histories
+    fn previous_moves(&self, ply: usize) -> PreviousMoves {
+        let entry_at = |distance: usize| {
+            if ply < distance {
+                return None;
+            }
+            let entry = self.stack[ply - distance];
+            Some((entry.moved_piece?, entry.current_move?.get_to()))
+        };
+
+        [entry_at(1), entry_at(2)]
+    }
+}
+
+/// Lazy SMP: the main thread and the helper threads all search the same root with their own board and
+/// stacks, and only share the transposition table. When the main thread stops, the helpers are stopped
+/// too and the move is chosen by a vote weighted by the depth and score each thread reached.
 pub fn iterative_deepening_search(
     board: &mut Board,
-    think_time_ms: u64,
+    tt: &mut TranspositionTable,
+    limits: &SearchLimits,
     stop_flag: &Arc<AtomicBool>,
+    ponder_flag: &AtomicBool,
+    options: &SearchOptions,
 ) {
-    let mut best_search_res: (Move, i32) = (board.get_legal_moves().index(0), i32::MIN);
-    let mut best_depth = 0u8;
-    let think_time = Duration::from_millis(think_time_ms);
+    let now = Instant::now();
 
-    let mut tt = TranspositionTable::new(64); // 64 MB
+    if board.get_legal_moves().len() == 0 {
+        wait_for_stop(limits, stop_flag, ponder_flag);
+        println!("bestmove 0000");
+        return;
+    }
 
-    let now = Instant::now();
-    for current_depth in 1..=32 {
-        let search_res_opt =
-            alpha_beta_root_node(board, current_depth, &mut tt, now, think_time, stop_flag);
-        if let Some(search_res) = search_res_opt {
-            best_search_res = search_res;
-            best_depth = current_depth;
-
-            if best_search_res.1 >= MATE_THRESHOLD {
+    tt.increment_age();
+    let tt = &*tt;
+
+    let skill = Skill::from_options(options);
+    let skill_limits;
+    let limits = match &skill {
+        Some(skill) => {
+            skill_limits = skill.limit(limits);
+            &skill_limits
+        }
+        None => limits,
+    };
+    let multi_pv = skill
+        .as_ref()
+        .map_or(options.multi_pv, |skill| skill.multi_pv(options.multi_pv));
+
+    let mut time_manager = TimeManager::new(
+        limits,
+        board.get_color_to_move(),
+        options.move_overhead_ms,
+        options.ponder,
+    );
+    let num_threads = options.threads.max(1);
+    let thread_nodes: Vec<AtomicU64> = (0..num_threads).map(|_| AtomicU64::new(0)).collect();
+    let helpers_stop_flag = Arc::new(AtomicBool::new(false));
+
+    let results = thread::scope(|scope| {
+        let helpers: Vec<_> = (1..num_threads)
+            .map(|thread_id| {
+                let mut helper_board = board.clone();
+                let (thread_nodes, helpers_stop_flag) = (&thread_nodes, &helpers_stop_flag);
+
+                thread::Builder::new()
+                    .stack_size(SEARCH_THREAD_STACK_SIZE)
+                    .spawn_scoped(scope, move || {
+                        let mut ctx = SearchContext::new(
+                            tt,
+                            now,
+                            None,
+                            None,
+                            helpers_stop_flag,
+                            thread_nodes,
+                            thread_id,
+                        );
+                        // only the lines of the main thread are reported, the helpers just deepen
+                        // the best one and share what they find through the table
+                        search_thread(&mut helper_board, &mut ctx, limits, 1, None)
+                    })
+                    .unwrap()
+            })
+            .collect();
+
+        let mut ctx = SearchContext::new(
+            tt,
+            now,
+            time_manager.hard_limit(),
+            limits.nodes,
+            stop_flag,
+            &thread_nodes,
+            0,
+        );
+        ctx.ponder_flag = limits.ponder.then_some(ponder_flag);
+        let main_result = search_thread(board, &mut ctx, limits, multi_pv, Some(&mut time_manager));
+        helpers_stop_flag.store(true, Ordering::SeqCst);
+
+        let mut results = vec![main_result];
+        results.extend(helpers.into_iter().map(|helper| helper.join().unwrap()));
+
+        // the quiescence nodes and the pruning counters are only kept by the main thread
+        if options.debug {
+            println!(
+                "info string nodes {} main thread qnodes {}",
+                ctx.total_nodes(),
+                ctx.qnodes
+            );
+            println!("info string main thread pruning {}", ctx.pruning_stats);
+        }
+
+        results
+    });
+
+    // the lines reported with MultiPV are the ones of the main thread, so its best move is kept
+    let best_thread = if multi_pv > 1 {
+        0
+    } else {
+        vote_best_thread(&results)
+    };
+    if best_thread != 0 {
+        let result = &results[best_thread];
+        let elapsed_ms = now.elapsed().as_millis() as u64;
+        let nodes: u64 = thread_nodes.iter().map(|n| n.load(Ordering::Relaxed)).sum();
+        println!(
+            "info depth {} score {} nodes {} nps {} time {} pv {}",
+            result.depth,
+            score_to_uci(result.score),
+            nodes,
+            nodes * 1000 / elapsed_ms.max(1),
+            elapsed_ms,
+            pv_to_string(&result.pv)
+        );
+    }
+
+    // a weakened engine plays one of the lines of the last completed iteration
+    let (best_move, pv) = match &skill {
+        Some(skill) if !results[0].lines.is_empty() => {
+            let line = skill.pick_line(&results[0].lines, &mut Rng::from_time());
+            (line.pv[0], &line.pv[..])
+        }
+        _ => (results[best_thread].best_move, &results[best_thread].pv[..]),
+    };
+    let ponder_move = ponder_move(board, tt, best_move, pv);
+
+    wait_for_stop(limits, stop_flag, ponder_flag);
+    match ponder_move {
+        Some(ponder_move) => println!(
+            "bestmove {} ponder {}",
+            best_move.to_long_algebraic_notation(),
+            ponder_move.to_long_algebraic_notation()
+        ),
+        None => println!("bestmove {}", best_move.to_long_algebraic_notation()),
+    }
+}
+
+/// an infinite or ponder search may end by itself, on a mate or at the deepest iteration, but the bestmove
+/// must only be sent after the stop, or after the ponderhit when it has limits
+fn wait_for_stop(limits: &SearchLimits, stop_flag: &AtomicBool, ponder_flag: &AtomicBool) {
+    while (limits.is_infinite() || ponder_flag.load(Ordering::SeqCst))
+        && !stop_flag.load(Ordering::SeqCst)
+    {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// the expected reply to the best move, from the pv or else from the transposition table when the pv was
+/// cut short, checked to be legal because a table move may come from another position
+fn ponder_move(
+    board: &mut Board,
+    tt: &TranspositionTable,
+    best_move: Move,
+    pv: &[Move],
+) -> Option<Move> {
+    if pv.first() != Some(&best_move) {
+        return None;
+    }
+
+    board.make_move(best_move);
+    let ponder_move = pv
+        .get(1)
+        .copied()
+        .or_else(|| tt.probe(board.get_zobrist_key())?.best_move)
+        .filter(|&m| {
+            generate_legal_moves(board, true)
+                .iter()
+                .any(|legal| legal == m)
+        });
+    board.unmake_move(best_move);
+
+    ponder_move
+}
+
+/// Iterative deepening with aspiration windows, run by every search thread. Only the main thread gets the
+/// time manager, which decides after every iteration whether to start the next one.
+/// With MultiPV every iteration searches the root once per line, each time without the moves of the lines
+/// already found, so the lines come out best first.
+fn search_thread(
+    board: &mut Board,
+    ctx: &mut SearchContext,
+    limits: &SearchLimits,
+    multi_pv: usize,
+    mut time_manager: Option<&mut TimeManager>,
+) -> ThreadResult {
+    let mut result = ThreadResult {
+        depth: 0,
+        score: -INFINITY,
+        best_move: limits
+            .search_moves
+            .first()
+            .copied()
+            .unwrap_or_else(|| board.get_legal_moves().index(0)),
+        pv: Vec::new(),
+        lines: Vec::new(),
+    };
+
+    let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+    let num_root_moves = if limits.search_moves.is_empty() {
+        board.get_legal_moves().len() as usize
+    } else {
+        limits.search_moves.len()
+    };
+    let multi_pv = multi_pv.clamp(1, num_root_moves);
+
+    'iterative_deepening: for current_depth in 1..=max_depth {
+        // helpers skip some depths so that the threads don't all search the same tree
+        if ctx.thread_id > 0 {
+            let i = (ctx.thread_id - 1) % SKIP_SIZE.len();
+            if !((current_depth + SKIP_PHASE[i]) / SKIP_SIZE[i]).is_multiple_of(2) {
+                continue;
+            }
+        }
+
+        let mut lines: Vec<RootLine> = Vec::with_capacity(multi_pv);
+
+        for pv_index in 0..multi_pv {
+            ctx.seldepth = 0;
+
+            let excluded_moves: Vec<Move> = lines.iter().map(|line| line.pv[0]).collect();
+            let previous_line = result.lines.get(pv_index);
+            let previous_score = previous_line.map_or(-INFINITY, |line| line.score);
+            let previous_pv = previous_line.map_or(&[][..], |line| &line.pv[..]);
+
+            // the first iterations are too unstable to guess a window, and so are mate scores
+            let mut delta = ASPIRATION_DELTA;
+            let (mut alpha, mut beta) =
+                if current_depth >= ASPIRATION_MIN_DEPTH && previous_score.abs() < MATE_THRESHOLD {
+                    (
+                        (previous_score - delta).max(-INFINITY),
+                        (previous_score + delta).min(INFINITY),
+                    )
+                } else {
+                    (-INFINITY, INFINITY)
+                };
+
+            loop {
+                let Some(search_res) = alpha_beta_root_node(
+                    board,
+                    ctx,
+                    current_depth,
+                    alpha,
+                    beta,
+                    &excluded_moves,
+                    &limits.search_moves,
+                ) else {
+                    // the move, score and depth all come from the last completed iteration
+                    break 'iterative_deepening;
+                };
+
+                if search_res.1 <= alpha {
+                    // fail low: the previous best move is kept, the window is widened downwards
+                    report_search_info(
+                        ctx,
+                        current_depth,
+                        pv_index,
+                        search_res.1,
+                        " upperbound",
+                        previous_pv,
+                    );
+                    beta = (alpha + beta) / 2;
+                    alpha = (search_res.1 - delta).max(-INFINITY);
+                } else if search_res.1 >= beta {
+                    // fail high: the window is widened upwards
+                    report_search_info(
+                        ctx,
+                        current_depth,
+                        pv_index,
+                        search_res.1,
+                        " lowerbound",
+                        ctx.pv_table.line(0),
+                    );
+                    beta = (search_res.1 + delta).min(INFINITY);
+                } else {
+                    if pv_index == 0 {
+                        result.best_move = search_res.0;
+                        result.score = search_res.1;
+                        result.depth = current_depth;
+                        result.pv = ctx.pv_table.line(0).to_vec();
+                    }
+                    report_search_info(
+                        ctx,
+                        current_depth,
+                        pv_index,
+                        search_res.1,
+                        "",
+                        ctx.pv_table.line(0),
+                    );
+                    lines.push(RootLine {
+                        score: search_res.1,
+                        pv: ctx.pv_table.line(0).to_vec(),
+                    });
+                    break;
+                }
+
+                delta += delta / 2;
+            }
+        }
+
+        // a later line can come out above an earlier one when the search is unstable, the best one is played
+        lines.sort_by_key(|line| Reverse(line.score));
+        result.best_move = lines[0].pv[0];
+        result.score = lines[0].score;
+        result.pv = lines[0].pv.clone();
+        result.lines = lines;
+
+        // go mate keeps searching until a mate short enough is found, and MultiPV goes on to
+        // score the other lines
+        if let Some(mate) = limits.mate {
+            if MATE_SCORE - result.score < 2 * mate as i32 {
                 break;
             }
-        } else {
+        } else if result.score >= MATE_THRESHOLD && multi_pv == 1 {
             break;
         }
+
+        // the time manager keeps following the search while pondering, but can't stop it yet
+        if let Some(time_manager) = time_manager.as_deref_mut()
+            && time_manager.should_stop_after_iteration(
+                result.best_move,
+                result.score,
+                ctx.time_start.elapsed(),
+            )
+            && ctx.ponder_flag.is_none()
+        {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Each thread votes for its move with a weight growing with its depth and with how much its score is above
+/// the worst one. A thread that found a mate is trusted over the vote. Threads stopped before completing an
+/// iteration have no move of their own and don't vote.
+fn vote_best_thread(results: &[ThreadResult]) -> usize {
+    let searched = || results.iter().filter(|result| result.depth > 0);
+    let min_score = searched().map(|result| result.score).min().unwrap_or(0);
+    let mut votes: Vec<(Move, i64)> = Vec::new();
+
+    for result in searched() {
+        let weight = (result.score - min_score + 14) as i64 * result.depth as i64;
+        match votes.iter_mut().find(|(m, _)| *m == result.best_move) {
+            Some((_, vote)) => *vote += weight,
+            None => votes.push((result.best_move, weight)),
+        }
+    }
+
+    let votes_for = |result: &ThreadResult| {
+        votes
+            .iter()
+            .find(|(m, _)| *m == result.best_move)
+            .map_or(0, |(_, vote)| *vote)
+    };
+
+    let mut best_thread = 0;
+    for (thread_id, result) in results.iter().enumerate().skip(1) {
+        let best = &results[best_thread];
+        let better = if best.score >= MATE_THRESHOLD {
+            result.score > best.score
+        } else {
+            result.score >= MATE_THRESHOLD || votes_for(result) > votes_for(best)
+        };
+
+        if better && result.depth > 0 {
+            best_thread = thread_id;
+        }
+    }
+
+    best_thread
+}
+
+fn report_search_info(
+    ctx: &SearchContext,
+    depth: u8,
+    pv_index: usize,
+    score: i32,
+    bound: &str,
+    pv: &[Move],
+) {
+    if ctx.thread_id != 0 {
+        return;
     }
 
-    println!("info depth {} score cp {}", best_depth, best_search_res.1);
+    let elapsed_ms = ctx.now.elapsed().as_millis() as u64;
+    let nodes = ctx.total_nodes();
     println!(
-        "bestmove {}",
-        best_search_res.0.to_long_algebraic_notation()
+        "info depth {} seldepth {} multipv {} score {}{} nodes {} nps {} time {} hashfull {} pv {}",
+        depth,
+        ctx.seldepth,
+        pv_index + 1,
+        score_to_uci(score),
+        bound,
+        nodes,
+        nodes * 1000 / elapsed_ms.max(1),
+        elapsed_ms,
+        ctx.tt.hashfull(),
+        pv_to_string(pv)
     );
 }
 
+/// Searches all the root moves inside the (alpha, beta) window. The returned score is fail-soft,
+/// so it can be outside of the window, and the returned move is only reliable when it is above alpha.
+/// None is returned when the search is stopped, and the partial result is then thrown away.
+/// The excluded moves are the ones of the MultiPV lines already found, the search moves the ones given with
+/// go searchmoves (all of them when empty), and together they must leave at least one move.
 fn alpha_beta_root_node(
     board: &mut Board,
+    ctx: &mut SearchContext,
     max_depth: u8,
-    tt: &mut TranspositionTable,
-    now: Instant,
-    think_time: Duration,
-    stop_flag: &Arc<AtomicBool>,
+    mut alpha: i32,
+    beta: i32,
+    excluded_moves: &[Move],
+    search_moves: &[Move],
 ) -> Option<(Move, i32)> {
-    let mut alpha = -MATE_THRESHOLD;
-    let beta = MATE_THRESHOLD;
+    let original_alpha = alpha;
+    let mut best_score = -INFINITY;
     let mut best_move: Option<Move> = None;
 
-    tt.increment_age();
+    ctx.root_depth = max_depth;
+    ctx.stack[0].extensions = 0;
+    ctx.stack[0].excluded_move = None;
+    ctx.stack[1].extensions = 0;
 
     let zobrist_key = board.get_zobrist_key();
-    let tt_best_move = tt.probe(zobrist_key).and_then(|entry| entry.best_move);
+    let tt_best_move = ctx.tt.probe(zobrist_key).and_then(|entry| entry.best_move);
 
     let mut legal_moves = generate_legal_moves(board, true);
-    eval::order_moves(&mut legal_moves, board, tt_best_move);
+    eval::order_moves(&mut legal_moves, board, tt_best_move, |m| {
+        ctx.history.quiet_move_score(board, m, 0, &[None, None])
+    });
 
-    for m in legal_moves.iter() {
-        board.make_move(m);
+    ctx.pv_table.clear(0);
 
-        let this_move_eval: i32 =
-            if board.is_threefold_repetition() || board.draw_by_fifty_moves_rule() {
-                0
-            } else {
-                -alpha_beta(
-                    board,
-                    -beta,
-                    -alpha,
-                    max_depth - 1,
-                    tt,
-                    now,
-                    think_time,
-                    stop_flag,
-                )
-            };
+    let root_moves = legal_moves.iter().filter(|m| {
+        !excluded_moves.contains(m) && (search_moves.is_empty() || search_moves.contains(m))
+    });
+
+    for (i, m) in root_moves.enumerate() {
+        if ctx.thread_id == 0 && ctx.now.elapsed() >= CURRMOVE_REPORT_DELAY {
+            println!(
+                "info depth {} currmove {} currmovenumber {}",
+                max_depth,
+                m.to_long_algebraic_notation(),
+                i + 1
+            );
+        }
 
+        ctx.push_move(board, 0, m);
+        board.make_move(m);
+        ctx.tt.prefetch(board.get_zobrist_key());
+        ctx.nodes += 1;
+        let this_move_eval = if i == 0 {
+            -alpha_beta(board, ctx, -beta, -alpha, max_depth - 1, 1, true)
+        } else {
+            let null_window_eval =
+                -alpha_beta(board, ctx, -alpha - 1, -alpha, max_depth - 1, 1, false);
+            if null_window_eval > alpha && null_window_eval < beta {
+                -alpha_beta(board, ctx, -beta, -alpha, max_depth - 1, 1, true)
+            } else {
+                null_window_eval
+            }
+        };
         board.unmake_move(m);
 
-        if now.elapsed() >= think_time || stop_flag.load(Ordering::SeqCst) {
+        if ctx.should_stop() {
             return None;
         }
 
-        if this_move_eval > alpha {
+        if this_move_eval > best_score {
+            best_score = this_move_eval;
             best_move = Some(m);
-            alpha = this_move_eval;
+
+            if this_move_eval > alpha {
+                alpha = this_move_eval;
+                ctx.pv_table.update(0, m);
+            }
+        }
+
+        if alpha >= beta {
+            break;
         }
     }
 
-    // store best move in tt and return the tuple (m, alpha)
+    // store best move in tt and return the tuple (m, best_score), unless some moves were left out
     best_move.map(|m| {
-        tt.store(zobrist_key, max_depth, alpha, EntryType::Exact, Some(m));
-        (m, alpha)
+        if !excluded_moves.is_empty() || !search_moves.is_empty() {
+            return (m, best_score);
+        }
+
+        let entry_type = if best_score <= original_alpha {
+            EntryType::UpperBound
+        } else if best_score >= beta {
+            EntryType::LowerBound
+        } else {
+            EntryType::Exact
+        };
+
+        ctx.tt.store(
+            zobrist_key,
+            max_depth,
+            best_score,
+            None,
+            entry_type,
+            Some(m),
+        );
+        (m, best_score)
     })
 }
 
+/// Principal Variation Search: pv nodes are searched with an open window and only their first move is
+/// searched with it, every other node is searched with a null window (beta == alpha + 1)
 fn alpha_beta(
     board: &mut Board,
+    ctx: &mut SearchContext,
     mut alpha: i32,
     mut beta: i32,
     depth: u8,
-    tt: &mut TranspositionTable,
-    now: Instant,
-    think_time: Duration,
-    stop_flag: &Arc<AtomicBool>,
+    ply: usize,
+    pv_node: bool,
 ) -> i32 {
-    if now.elapsed() >= think_time || stop_flag.load(Ordering::SeqCst) {
+    ctx.pv_table.clear(ply);
+    ctx.seldepth = ctx.seldepth.max(ply);
+
+    if ctx.should_stop() {
         return 0;
     }
 
+    if board.is_threefold_repetition() || board.draw_by_fifty_moves_rule() {
+        return 0;
+    }
+
+    // mate distance pruning: even mating right now can't beat a shorter mate found elsewhere
+    alpha = alpha.max(-MATE_SCORE + ply as i32);
+    beta = beta.min(MATE_SCORE - ply as i32 - 1);
+    if alpha >= beta {
+        return alpha;
+    }
+
     let original_alpha = alpha;
-    let original_beta = beta;
     let zobrist_key = board.get_zobrist_key();
-    let tt_entry = tt.probe(zobrist_key);
-
-    if let Some(entry) = tt_entry {
-        if entry.depth >= depth {
-            match entry.entry_type {
-                tt::EntryType::Exact => return entry.score,
-                tt::EntryType::LowerBound => alpha = std::cmp::max(alpha, entry.score),
-                tt::EntryType::UpperBound => beta = std::cmp::min(beta, entry.score),
-            }
+    let tt_entry = ctx.tt.probe(zobrist_key);
+    // the singular extension search looks at the same position without one of its moves, so it neither
+    // takes cutoffs from nor stores into the tt, and doesn't prune the whole node either
+    let excluded_move = ctx.stack[ply].excluded_move;
 
-            if alpha >= beta {
-                return match entry.entry_type {
-                    EntryType::LowerBound => beta,
-                    EntryType::UpperBound => alpha,
-                    EntryType::Exact => unreachable!(),
-                };
-            }
+    // pv nodes don't take tt cutoffs, so that the principal variation is always searched in full
+    if !pv_node
+        && excluded_move.is_none()
+        && let Some(entry) = tt_entry
+        && entry.depth >= depth
+    {
+        let tt_score = score_from_tt(entry.score, ply);
+        match entry.entry_type {
+            EntryType::Exact => return tt_score,
+            EntryType::LowerBound if tt_score >= beta => return tt_score,
+            EntryType::UpperBound if tt_score <= alpha => return tt_score,
+            _ => {}
         }
     }
 
-    let mut max_eval = -MATE_THRESHOLD;
+    let mut max_eval = -INFINITY;
     let mut best_move: Option<Move> = None;
     let mut legal_moves = generate_legal_moves(board, true);
 
     if legal_moves.len() == 0 {
         if board.is_in_check() {
-            return max_eval - depth as i32; // shorter mates are preferred
+            return -MATE_SCORE + ply as i32;
         } else {
             return 0;
         }
     }
 
-    if depth == 0 {
-        return quiescence_search(board, alpha, beta, now, think_time, stop_flag);
+    if depth == 0 || ply >= MAX_PLY - 1 {
+        return quiescence_search(board, ctx, alpha, beta, ply);
+    }
+
+    let in_check = board.is_in_check();
+    // the static eval means nothing when in check, so none of the pruning below is done then
+    let static_eval = if in_check {
+        -INFINITY
+    } else {
+        tt_entry
+            .and_then(|entry| entry.static_eval)
+            .unwrap_or_else(|| eval::eval(board))
+    };
+    let tt_static_eval = (!in_check).then_some(static_eval);
+
+    if !pv_node
+        && !in_check
+        && excluded_move.is_none()
+        && depth <= RFP_MAX_DEPTH
+        && beta.abs() < MATE_THRESHOLD
+    {
+        let prune = static_eval - RFP_MARGIN * depth as i32 >= beta;
+        ctx.pruning_stats.reverse_futility.record(prune);
+        if prune {
+            return static_eval;
+        }
+    }
+
+    if !pv_node
+        && !in_check
+        && excluded_move.is_none()
+        && depth <= RAZOR_MAX_DEPTH
+        && alpha.abs() < MATE_THRESHOLD
+        && static_eval + RAZOR_MARGIN * (depth as i32) < alpha
+    {
+        let razor_eval = quiescence_search(board, ctx, alpha, alpha + 1, ply);
+        if ctx.stopped {
+            return 0;
+        }
+        let prune = razor_eval <= alpha;
+        ctx.pruning_stats.razoring.record(prune);
+        if prune {
+            return razor_eval;
+        }
+    }
+
+    if !pv_node
+        && !in_check
+        && excluded_move.is_none()
+        && depth >= NMP_MIN_DEPTH
+        && ply >= ctx.nmp_min_ply
+        && !ctx.stack[ply - 1].null_move
+        && beta.abs() < MATE_THRESHOLD
+        && board.has_non_pawn_material(board.get_color_to_move())
+        && static_eval >= beta
+    {
+        let reduction = NMP_BASE_REDUCTION + depth / NMP_DEPTH_DIVISOR;
+        let null_move_depth = depth.saturating_sub(1 + reduction);
+
+        ctx.push_null_move(ply);
+        board.make_null_move();
+        let null_move_eval = -alpha_beta(
+            board,
+            ctx,
+            -beta,
+            -beta + 1,
+            null_move_depth,
+            ply + 1,
+            false,
+        );
+        board.unmake_null_move();
+        if ctx.stopped {
+            return 0;
+        }
+
+        if null_move_eval >= beta {
+            // a mate found after passing the turn is not a real mate
+            let null_move_eval = null_move_eval.min(MATE_THRESHOLD - 1);
+
+            if depth < NMP_VERIFICATION_DEPTH || ctx.nmp_min_ply != 0 {
+                ctx.pruning_stats.null_move.record(true);
+                return null_move_eval;
+            }
+
+            ctx.nmp_min_ply = ply + 3 * null_move_depth as usize / 4;
+            let verification_eval =
+                alpha_beta(board, ctx, beta - 1, beta, null_move_depth, ply, false);
+            ctx.nmp_min_ply = 0;
+            if ctx.stopped {
+                return 0;
+            }
+
+            if verification_eval >= beta {
+                ctx.pruning_stats.null_move.record(true);
+                return null_move_eval;
+            }
+        }
+
+        ctx.pruning_stats.null_move.record(false);
     }
 
+    // quiet moves are only pruned when neither bound is a mate score
+    let can_prune_quiets = !pv_node && !in_check && alpha.abs() < MATE_THRESHOLD;
+    let futility_margin = FUTILITY_BASE_MARGIN + FUTILITY_MARGIN * depth as i32;
+
+    // singular extension: the tt move is extended when no other move comes close to its score
+    let mut singular_move = None;
+    if let Some(entry) = tt_entry
+        && let Some(tt_move) = entry.best_move
+        && excluded_move.is_none()
+        && depth >= SE_MIN_DEPTH
+        && entry.depth + SE_MAX_TT_DEPTH_GAP >= depth
+        && matches!(entry.entry_type, EntryType::Exact | EntryType::LowerBound)
+        && score_from_tt(entry.score, ply).abs() < MATE_THRESHOLD
+        && ctx.stack[ply].extensions < ctx.root_depth / 2
+    {
+        let singular_beta = score_from_tt(entry.score, ply) - SE_MARGIN * depth as i32;
+
+        ctx.stack[ply].excluded_move = Some(tt_move);
+        let singular_eval = alpha_beta(
+            board,
+            ctx,
+            singular_beta - 1,
+            singular_beta,
+            (depth - 1) / 2,
+            ply,
+            false,
+        );
+        ctx.stack[ply].excluded_move = None;
+        ctx.pv_table.clear(ply);
+        if ctx.stopped {
+            return 0;
+        }
+
+        if singular_eval < singular_beta {
+            singular_move = Some(tt_move);
+        }
+    }
+
+    let previous_moves = ctx.previous_moves(ply);
     let tt_best_move = tt_entry.and_then(|entry| entry.best_move);
-    eval::order_moves(&mut legal_moves, board, tt_best_move);
+    eval::order_moves(&mut legal_moves, board, tt_best_move, |m| {
+        ctx.history.quiet_move_score(board, m, ply, &previous_moves)
+    });
+
+    ctx.history.clear_killers(ply + 1);
+    let mut quiets_searched = MoveList::new();
+
+    for (i, m) in legal_moves.iter().enumerate() {
+        if excluded_move == Some(m) {
+            continue;
+        }
+
+        let is_capture = board.get_piece_at(m.get_to()).is_some();
+        let is_tactical = m.is_promotion() || m.is_enpassant() || is_capture;
+        let quiet_history = if is_tactical {
+            0
+        } else {
+            ctx.history.quiet_history(board, m, &previous_moves)
+        };
+        let is_countermove = !is_tactical && ctx.history.is_countermove(m, &previous_moves);
+        let is_recapture = is_capture
+            && ctx.stack[ply - 1].capture
+            && ctx.stack[ply - 1]
+                .current_move
+                .is_some_and(|previous_move| previous_move.get_to() == m.get_to());
 
-    for m in legal_moves.iter() {
+        ctx.push_move(board, ply, m);
         board.make_move(m);
+        let gives_check = board.is_in_check();
+
+        // moves that give check are never pruned, and at least one move is searched so that a pruned
+        // node doesn't look like a mate
+        if can_prune_quiets && !is_tactical && max_eval > -MATE_THRESHOLD && !gives_check {
+            if depth <= LMP_MAX_DEPTH {
+                let prune = i >= LMP_BASE_MOVES + (depth as usize * depth as usize);
+                ctx.pruning_stats.late_move.record(prune);
+                if prune {
+                    board.unmake_move(m);
+                    continue;
+                }
+            }
 
-        let this_move_eval = if board.is_threefold_repetition() || board.draw_by_fifty_moves_rule()
+            if depth <= FUTILITY_MAX_DEPTH {
+                let prune = static_eval + futility_margin <= alpha;
+                ctx.pruning_stats.futility.record(prune);
+                if prune {
+                    board.unmake_move(m);
+                    continue;
+                }
+            }
+        }
+
+        ctx.tt.prefetch(board.get_zobrist_key());
+        ctx.nodes += 1;
+
+        // at most one ply per move, and at most half the depth of the iteration on a path
+        let extension = if ctx.stack[ply].extensions < ctx.root_depth / 2
+            && (gives_check || singular_move == Some(m) || (pv_node && is_recapture))
         {
+            1
+        } else {
             0
+        };
+        ctx.stack[ply + 1].extensions = ctx.stack[ply].extensions + extension;
+        let new_depth = depth - 1 + extension;
+
+        let mut reduction = 0;
+        if depth >= LMR_MIN_DEPTH && i >= LMR_MIN_MOVE_INDEX + pv_node as usize {
+            reduction = LMR_REDUCTIONS[(depth as usize).min(63)][i.min(63)];
+
+            if pv_node {
+                reduction -= 1;
+            }
+            if in_check || gives_check {
+                reduction -= 1;
+            }
+            if is_tactical {
+                reduction -= 1;
+            }
+            // the countermove refuted the previous move before, like a good history it is reduced less
+            if is_countermove {
+                reduction -= 1;
+            }
+            reduction -= quiet_history / LMR_HISTORY_DIVISOR;
+
+            // always leave at least one ply to search
+            reduction = reduction.clamp(0, new_depth as i32 - 1);
+        }
+
+        let this_move_eval = if pv_node && i == 0 {
+            -alpha_beta(board, ctx, -beta, -alpha, new_depth, ply + 1, true)
         } else {
-            -alpha_beta(
+            let mut null_window_eval = -alpha_beta(
                 board,
-                -beta,
+                ctx,
+                -alpha - 1,
                 -alpha,
-                depth - 1,
-                tt,
-                now,
-                think_time,
-                stop_flag,
-            )
+                new_depth - reduction as u8,
+                ply + 1,
+                false,
+            );
+
+            // a reduced move that beats alpha is searched again at full depth
+            if reduction > 0 && null_window_eval > alpha {
+                null_window_eval =
+                    -alpha_beta(board, ctx, -alpha - 1, -alpha, new_depth, ply + 1, false);
+            }
+
+            // in a non pv node the null window is already the full window
+            if pv_node && null_window_eval > alpha && null_window_eval < beta {
+                -alpha_beta(board, ctx, -beta, -alpha, new_depth, ply + 1, true)
+            } else {
+                null_window_eval
+            }
         };
         board.unmake_move(m);
 
+        if ctx.stopped {
+            return 0;
+        }
+
         if this_move_eval > max_eval {
             max_eval = this_move_eval;
             best_move = Some(m);
 
             if this_move_eval > alpha {
                 alpha = this_move_eval;
+                ctx.pv_table.update(ply, m);
             }
         }
 
         if alpha >= beta {
             // beta cutoff
-            tt.store(zobrist_key, depth, beta, EntryType::LowerBound, best_move);
+            if !is_tactical {
+                ctx.history.update_quiet_stats(
+                    board,
+                    ply,
+                    m,
+                    quiets_searched.iter(),
+                    depth,
+                    &previous_moves,
+                );
+            }
+
+            if excluded_move.is_none() {
+                ctx.tt.store(
+                    zobrist_key,
+                    depth,
+                    score_to_tt(beta, ply),
+                    tt_static_eval,
+                    EntryType::LowerBound,
+                    best_move,
+                );
+            }
             return max_eval;
         }
+
+        if !is_tactical {
+            quiets_searched.push(m);
+        }
+    }
+
+    if excluded_move.is_some() {
+        return max_eval;
     }
 
     let entry_type = if max_eval <= original_alpha {
         EntryType::UpperBound
-    } else if max_eval >= original_beta {
-        EntryType::LowerBound
     } else {
         EntryType::Exact
     };
 
-    tt.store(zobrist_key, depth, max_eval, entry_type, best_move);
+    ctx.tt.store(
+        zobrist_key,
+        depth,
+        score_to_tt(max_eval, ply),
+        tt_static_eval,
+        entry_type,
+        best_move,
+    );
 
     max_eval
 }
 
+/// Searches captures until the position is quiet. A side in check can't stand pat, so it searches all its
+/// evasions instead and can be mated.
 fn quiescence_search(
     board: &mut Board,
+    ctx: &mut SearchContext,
     mut alpha: i32,
     beta: i32,
-    now: Instant,
-    think_time: Duration,
-    stop_flag: &Arc<AtomicBool>,
+    ply: usize,
 ) -> i32 {
-    if now.elapsed() >= think_time || stop_flag.load(Ordering::SeqCst) {
+    ctx.seldepth = ctx.seldepth.max(ply);
+
+    if ctx.should_stop() {
         return 0;
     }
 
-    let stand_pat = eval::eval(board);
+    let in_check = board.is_in_check();
+
+    if ply >= MAX_PLY - 1 {
+        return if in_check { 0 } else { eval::eval(board) };
+    }
+
+    let original_alpha = alpha;
+    let zobrist_key = board.get_zobrist_key();
+    let tt_entry = ctx.tt.probe(zobrist_key);
+
+    // every entry is at least as deep as a quiescence search
+    if let Some(entry) = tt_entry {
+        let tt_score = score_from_tt(entry.score, ply);
+        match entry.entry_type {
+            EntryType::Exact => return tt_score,
+            EntryType::LowerBound if tt_score >= beta => return tt_score,
+            EntryType::UpperBound if tt_score <= alpha => return tt_score,
+            _ => {}
+        }
+    }
+
+    let mut moves = generate_legal_moves(board, in_check);
+
+    if in_check && moves.len() == 0 {
+        return -MATE_SCORE + ply as i32;
+    }
+
+    let stand_pat = if in_check {
+        -INFINITY
+    } else {
+        tt_entry
+            .and_then(|entry| entry.static_eval)
+            .unwrap_or_else(|| eval::eval(board))
+    };
+    let tt_static_eval = (!in_check).then_some(stand_pat);
 
     if stand_pat >= beta {
         return stand_pat;
     }
 
-    if stand_pat > alpha {
-        alpha = stand_pat;
-    }
+    alpha = alpha.max(stand_pat);
 
-    let mut captures = generate_legal_moves(board, false);
-    eval::order_moves(&mut captures, board, None);
+    let mut max_eval = stand_pat;
+    let mut best_move: Option<Move> = None;
+
+    let tt_best_move = tt_entry.and_then(|entry| entry.best_move);
+    eval::order_moves(&mut moves, board, tt_best_move, |_| 0);
+
+    for m in moves.iter() {
+        if !in_check && !m.is_promotion() {
+            // delta pruning: winning the captured piece and then some still doesn't reach alpha
+            let captured_value = if m.is_enpassant() {
+                SEE_VALUES[PieceType::Pawn]
+            } else {
+                board
+                    .get_piece_at(m.get_to())
+                    .map_or(0, |piece| SEE_VALUES[piece.get_type()])
+            };
+            let prune = stand_pat + captured_value + DELTA_MARGIN <= alpha;
+            ctx.pruning_stats.delta.record(prune);
+            if prune {
+                continue;
+            }
+
+            // captures that lose material in the exchange
+            let prune = !see::see_ge(board, m, 0);
+            ctx.pruning_stats.see.record(prune);
+            if prune {
+                continue;
+            }
+        }
 
-    for m in captures.iter() {
         board.make_move(m);
+        ctx.tt.prefetch(board.get_zobrist_key());
+        ctx.nodes += 1;
+        ctx.qnodes += 1;
         let this_move_evaluation =
             if board.is_threefold_repetition() || board.draw_by_fifty_moves_rule() {
                 0
             } else {
-                -quiescence_search(board, -beta, -alpha, now, think_time, stop_flag)
+                -quiescence_search(board, ctx, -beta, -alpha, ply + 1)
             };
 
         board.unmake_move(m);
 
+        if ctx.stopped {
+            return 0;
+        }
+
+        if this_move_evaluation > max_eval {
+            max_eval = this_move_evaluation;
+            best_move = Some(m);
+        }
+
         if this_move_evaluation >= beta {
-            return this_move_evaluation;
+            break;
+        }
+
+        alpha = alpha.max(this_move_evaluation);
+    }
+
+    let entry_type = if max_eval >= beta {
+        EntryType::LowerBound
+    } else if max_eval > original_alpha {
+        EntryType::Exact
+    } else {
+        EntryType::UpperBound
+    };
+
+    ctx.tt.store(
+        zobrist_key,
+        0,
+        score_to_tt(max_eval, ply),
+        tt_static_eval,
+        entry_type,
+        best_move,
+    );
+
+    max_eval
+}
+
+/// mate scores are stored in the tt as distance from the node instead of distance from the root,
+/// so that they stay correct when the same position is reached at a different ply
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_THRESHOLD {
+        score + ply as i32
+    } else if score <= -MATE_THRESHOLD {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_THRESHOLD {
+        score - ply as i32
+    } else if score <= -MATE_THRESHOLD {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// "cp x" or "mate n" with n counted in moves, negative when the engine is getting mated
+fn score_to_uci(score: i32) -> String {
+    if score >= MATE_THRESHOLD {
+        format!("mate {}", (MATE_SCORE - score + 1) / 2)
+    } else if score <= -MATE_THRESHOLD {
+        format!("mate {}", -(MATE_SCORE + score) / 2)
+    } else {
+        format!("cp {}", score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const PROMOTIONS: &str = "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R w KQkq - 0 1";
+
+    // with --features board-invariants every move made and unmade by the search is checked as well
+    #[test]
+    fn fixed_depth_search_leaves_the_board_unchanged() {
+        for fen in [KIWIPETE, PROMOTIONS] {
+            let mut board = Board::new(fen);
+            let mut tt = TranspositionTable::new(1);
+            let limits = SearchLimits {
+                depth: Some(6),
+                ..Default::default()
+            };
+            let options = SearchOptions {
+                threads: 2,
+                ..Default::default()
+            };
+
+            iterative_deepening_search(
+                &mut board,
+                &mut tt,
+                &limits,
+                &Arc::new(AtomicBool::new(false)),
+                &AtomicBool::new(false),
+                &options,
+            );
+
+            let original = Board::new(fen);
+            assert_eq!(board.to_fen(), original.to_fen());
+            assert_eq!(board.get_zobrist_key(), original.get_zobrist_key());
         }
+    }
 
-        alpha = std::cmp::max(this_move_evaluation, alpha);
+    #[test]
+    fn threads_without_an_iteration_do_not_vote() {
+        let result = |depth, score, m: u16| ThreadResult {
+            depth,
+            score,
+            best_move: Move::from(m),
+            pv: Vec::new(),
+            lines: Vec::new(),
+        };
+        let results = [result(6, 0, 1), result(0, -INFINITY, 2), result(5, 200, 3)];
+
+        // the much better score of the third thread outweighs the extra depth of the first one
+        assert_eq!(vote_best_thread(&results), 2);
+    }
+
+    #[test]
+    fn tt_scores_round_trip() {
+        for ply in [0, 1, 7, MAX_PLY - 1] {
+            for score in [
+                MATE_SCORE - 1,
+                MATE_THRESHOLD,
+                -MATE_SCORE + 2,
+                -MATE_THRESHOLD,
+                0,
+                350,
+                -MATE_THRESHOLD + 1,
+            ] {
+                assert_eq!(score_from_tt(score_to_tt(score, ply), ply), score);
+            }
+        }
     }
 
-    alpha
+    #[test]
+    fn tt_mate_scores_count_from_the_node() {
+        // mating 2 plies after a node at ply 10, found again at ply 4
+        let stored = score_to_tt(MATE_SCORE - 12, 10);
+        assert_eq!(stored, MATE_SCORE - 2);
+        assert_eq!(score_from_tt(stored, 4), MATE_SCORE - 6);
+
+        let stored = score_to_tt(-MATE_SCORE + 13, 10);
+        assert_eq!(stored, -MATE_SCORE + 3);
+        assert_eq!(score_from_tt(stored, 4), -MATE_SCORE + 7);
+    }
+
+    #[test]
+    fn mate_scores_are_reported_in_moves() {
+        assert_eq!(score_to_uci(MATE_SCORE - 1), "mate 1");
+        assert_eq!(score_to_uci(MATE_SCORE - 5), "mate 3");
+        assert_eq!(score_to_uci(-MATE_SCORE + 2), "mate -1");
+        assert_eq!(score_to_uci(-MATE_SCORE + 6), "mate -3");
+        assert_eq!(
+            score_to_uci(MATE_THRESHOLD - 1),
+            format!("cp {}", MATE_THRESHOLD - 1)
+        );
+        assert_eq!(score_to_uci(-25), "cp -25");
+    }
 }
@@ -1,5 +1,9 @@
 use crate::{
-    board::{Board, bitboard::Bitmanip, piece::PieceColor},
+    board::{
+        Board,
+        bitboard::Bitmanip,
+        piece::{PieceColor, PieceType},
+    },
     move_gen::{chess_move::Move, move_list::MoveList},
 };
 
@@ -219,24 +223,46 @@ pub fn eval(board: &Board) -> i32 {
     (material_score + positional_score) * COLOR_MULTIPLIERS[board.get_color_to_move()]
 }
 
-pub fn order_moves(moves: &mut MoveList, board: &Board, best_tt_move: Option<Move>) {
+// captures and promotions are always ordered before the quiet moves, whose scores come from the search history
+const TACTICAL_MOVE_BASE_SCORE: i32 = 1 << 20;
+
+pub fn order_moves(
+    moves: &mut MoveList,
+    board: &Board,
+    best_tt_move: Option<Move>,
+    quiet_move_score: impl Fn(Move) -> i32,
+) {
     let mut scores: Vec<i32> = vec![0; moves.len() as usize];
 
     for (i, m) in moves.iter().enumerate() {
-        if let Some(tt_move) = best_tt_move {
-            if m == tt_move {
-                scores[i] = i32::MAX;
-                continue; // skip otherwise it's overwritten or may overflow
-            }
+        if let Some(tt_move) = best_tt_move
+            && m == tt_move
+        {
+            scores[i] = i32::MAX;
+            continue; // skip otherwise it's overwritten or may overflow
         }
 
+        let mut is_tactical = false;
+
         if let Some(captured_piece) = board.get_piece_at(m.get_to()) {
-            scores[i] = PIECE_WEIGHTS[captured_piece.get_type()] * 10
+            scores[i] = TACTICAL_MOVE_BASE_SCORE + PIECE_WEIGHTS[captured_piece.get_type()] * 10
                 - PIECE_WEIGHTS[board.get_piece_at(m.get_from()).unwrap().get_type()];
+            is_tactical = true;
+        } else if m.is_enpassant() {
+            scores[i] = TACTICAL_MOVE_BASE_SCORE + PIECE_WEIGHTS[PieceType::Pawn] * 9;
+            is_tactical = true;
         }
 
         if m.is_promotion() {
+            if !is_tactical {
+                scores[i] = TACTICAL_MOVE_BASE_SCORE;
+            }
             scores[i] += PIECE_WEIGHTS[m.get_promotion_type()];
+            is_tactical = true;
+        }
+
+        if !is_tactical {
+            scores[i] = quiet_move_score(m);
         }
     }
 
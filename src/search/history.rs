@@ -0,0 +1,156 @@
+use super::MAX_PLY;
+use crate::{
+    board::{Board, piece::Piece},
+    move_gen::chess_move::Move,
+};
+
+// history scores are kept in [-MAX_HISTORY, MAX_HISTORY] by the gravity formula in update_history
+pub const MAX_HISTORY: i32 = 16384;
+const MAX_HISTORY_BONUS: i32 = 1200;
+
+// quiet moves are ordered after the captures: killers, then the countermove, then by the sum of the
+// butterfly and continuation histories, which is at most 3 * MAX_HISTORY
+const FIRST_KILLER_SCORE: i32 = 3 * MAX_HISTORY + 3;
+const SECOND_KILLER_SCORE: i32 = 3 * MAX_HISTORY + 2;
+const COUNTERMOVE_SCORE: i32 = 3 * MAX_HISTORY + 1;
+
+/// Piece that moved and its landing square, for the moves played one and two plies before the current node.
+/// None when there is no such move or it was a null move.
+pub type PreviousMoves = [Option<(Piece, u64)>; 2];
+
+type ContinuationTable = [[[i32; 64]; 12]; 64];
+
+/// Move ordering heuristics for quiet moves, learned during the search
+pub struct SearchHistory {
+    killers: [[Option<Move>; 2]; MAX_PLY + 1],
+    butterfly: [[[i32; 64]; 64]; 2],
+    // [previous piece][previous to]
+    countermoves: [[Option<Move>; 64]; 12],
+    // [previous piece][previous to][piece][to], shared by the one and two plies continuations
+    continuation: Vec<ContinuationTable>,
+}
+
+impl SearchHistory {
+    pub fn new() -> Self {
+        SearchHistory {
+            killers: [[None; 2]; MAX_PLY + 1],
+            butterfly: [[[0; 64]; 64]; 2],
+            countermoves: [[None; 64]; 12],
+            continuation: vec![[[[0; 64]; 12]; 64]; 12],
+        }
+    }
+
+    #[inline]
+    pub fn clear_killers(&mut self, ply: usize) {
+        self.killers[ply] = [None; 2];
+    }
+
+    /// butterfly history plus the continuation histories of the previous two moves
+    pub fn quiet_history(&self, board: &Board, m: Move, previous_moves: &PreviousMoves) -> i32 {
+        let piece = board.get_piece_at(m.get_from()).unwrap();
+        let mut res = self.butterfly[piece.get_color()][m.get_from() as usize][m.get_to() as usize];
+
+        for &(previous_piece, previous_to) in previous_moves.iter().flatten() {
+            res += self.continuation[piece_index(previous_piece)][previous_to as usize]
+                [piece_index(piece)][m.get_to() as usize];
+        }
+
+        res
+    }
+
+    /// whether the move refuted the previous move the last time it was played
+    pub fn is_countermove(&self, m: Move, previous_moves: &PreviousMoves) -> bool {
+        previous_moves[0].is_some_and(|(previous_piece, previous_to)| {
+            self.countermoves[piece_index(previous_piece)][previous_to as usize] == Some(m)
+        })
+    }
+
+    pub fn quiet_move_score(
+        &self,
+        board: &Board,
+        m: Move,
+        ply: usize,
+        previous_moves: &PreviousMoves,
+    ) -> i32 {
+        if self.killers[ply][0] == Some(m) {
+            FIRST_KILLER_SCORE
+        } else if self.killers[ply][1] == Some(m) {
+            SECOND_KILLER_SCORE
+        } else if self.is_countermove(m, previous_moves) {
+            COUNTERMOVE_SCORE
+        } else {
+            self.quiet_history(board, m, previous_moves)
+        }
+    }
+
+    /// Called when a quiet move causes a beta cutoff: the move becomes a killer and the countermove of the
+    /// previous move, and gets a history bonus, while the quiet moves searched before it get the same amount
+    /// as a malus
+    pub fn update_quiet_stats(
+        &mut self,
+        board: &Board,
+        ply: usize,
+        cutoff_move: Move,
+        quiets_searched: impl Iterator<Item = Move>,
+        depth: u8,
+        previous_moves: &PreviousMoves,
+    ) {
+        if self.killers[ply][0] != Some(cutoff_move) {
+            self.killers[ply][1] = self.killers[ply][0];
+            self.killers[ply][0] = Some(cutoff_move);
+        }
+
+        if let Some((previous_piece, previous_to)) = previous_moves[0] {
+            self.countermoves[piece_index(previous_piece)][previous_to as usize] =
+                Some(cutoff_move);
+        }
+
+        let bonus = history_bonus(depth);
+        self.update_move_history(board, cutoff_move, bonus, previous_moves);
+
+        for m in quiets_searched {
+            if m != cutoff_move {
+                self.update_move_history(board, m, -bonus, previous_moves);
+            }
+        }
+    }
+
+    fn update_move_history(
+        &mut self,
+        board: &Board,
+        m: Move,
+        bonus: i32,
+        previous_moves: &PreviousMoves,
+    ) {
+        let piece = board.get_piece_at(m.get_from()).unwrap();
+
+        update_history(
+            &mut self.butterfly[piece.get_color()][m.get_from() as usize][m.get_to() as usize],
+            bonus,
+        );
+
+        for &(previous_piece, previous_to) in previous_moves.iter().flatten() {
+            update_history(
+                &mut self.continuation[piece_index(previous_piece)][previous_to as usize]
+                    [piece_index(piece)][m.get_to() as usize],
+                bonus,
+            );
+        }
+    }
+}
+
+#[inline]
+fn piece_index(piece: Piece) -> usize {
+    piece.get_color() as usize * 6 + piece.get_type() as usize
+}
+
+#[inline]
+fn history_bonus(depth: u8) -> i32 {
+    (16 * depth as i32 * depth as i32).min(MAX_HISTORY_BONUS)
+}
+
+/// the closer the entry is to MAX_HISTORY the smaller the effect of a bonus in the same direction
+#[inline]
+fn update_history(entry: &mut i32, bonus: i32) {
+    *entry += bonus - *entry * bonus.abs() / MAX_HISTORY;
+}
@@ -1,7 +1,10 @@
+use crate::board::bitboard::Bitmanip;
 use crate::move_gen::generate_legal_moves;
-use std::sync::atomic::Ordering;
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::{
-    sync::{atomic::AtomicBool, Arc},
+    sync::{Arc, atomic::AtomicBool},
+    thread,
     time::Instant,
 };
 
@@ -18,6 +21,7 @@ pub fn perft_test(max_depth: u8, board: &mut Board, stop: &Arc<AtomicBool>) {
         let num_positions = perft_test_r(i, board, i, stop);
 
         if stop.load(Ordering::SeqCst) {
+            println!("Stopped during depth {}", i);
             return;
         }
 
@@ -63,16 +67,379 @@ fn perft_test_r(depth: u8, board: &mut Board, max_depth: u8, stop: &Arc<AtomicBo
     res
 }
 
-fn _square_to_str(index: u64) -> String {
-    let y = index / 8;
-    let x = index % 8;
+/// Same columns as the tables published on https://www.chessprogramming.org/Perft_Results,
+/// all of them counted on the moves made at the last ply
+#[derive(Clone, Copy, Default)]
+pub struct PerftStats {
+    pub nodes: u128,
+    pub captures: u128,
+    pub enpassants: u128,
+    pub castles: u128,
+    pub promotions: u128,
+    pub checks: u128,
+    pub discovered_checks: u128,
+    pub double_checks: u128,
+    pub checkmates: u128,
+}
+
+impl AddAssign for PerftStats {
+    fn add_assign(&mut self, other: Self) {
+        self.nodes += other.nodes;
+        self.captures += other.captures;
+        self.enpassants += other.enpassants;
+        self.castles += other.castles;
+        self.promotions += other.promotions;
+        self.checks += other.checks;
+        self.discovered_checks += other.discovered_checks;
+        self.double_checks += other.double_checks;
+        self.checkmates += other.checkmates;
+    }
+}
+
+pub fn perft_stats_test(max_depth: u8, board: &mut Board, stop: &Arc<AtomicBool>) {
+    println!("---------------------------------------");
+    println!("|                                     |");
+    println!("|  Running Perft stats with depth: {:>2} |", max_depth);
+    println!("|                                     |");
+    println!("---------------------------------------");
+    println!(
+        "Depth | {:>12} | {:>10} | {:>8} | {:>8} | {:>8} | {:>10} | {:>8} | {:>8} | {:>8}",
+        "Nodes", "Captures", "E.p.", "Castles", "Promos", "Checks", "Disc.", "Double", "Mates"
+    );
+    for i in 1..=max_depth {
+        let stats = perft_stats_r(i, board, stop);
+
+        if stop.load(Ordering::SeqCst) {
+            println!("Stopped during depth {}", i);
+            return;
+        }
+
+        println!(
+            "{:>5} | {:>12} | {:>10} | {:>8} | {:>8} | {:>8} | {:>10} | {:>8} | {:>8} | {:>8}",
+            i,
+            stats.nodes,
+            stats.captures,
+            stats.enpassants,
+            stats.castles,
+            stats.promotions,
+            stats.checks,
+            stats.discovered_checks,
+            stats.double_checks,
+            stats.checkmates
+        );
+    }
+}
+
+fn perft_stats_r(depth: u8, board: &mut Board, stop: &Arc<AtomicBool>) -> PerftStats {
+    let mut res = PerftStats::default();
+    if stop.load(Ordering::SeqCst) {
+        return res;
+    }
+
+    let legal_moves = generate_legal_moves(board, true);
+
+    for m in legal_moves.iter() {
+        if depth > 1 {
+            board.make_move(m);
+            res += perft_stats_r(depth - 1, board, stop);
+            board.unmake_move(m);
+            continue;
+        }
+
+        res.nodes += 1;
+
+        if m.is_enpassant() {
+            res.captures += 1;
+            res.enpassants += 1;
+        } else if board.get_piece_at(m.get_to()).is_some() {
+            res.captures += 1;
+        }
+
+        if m.is_castle_kingside() || m.is_castle_queenside() {
+            res.castles += 1;
+        }
+
+        if m.is_promotion() {
+            res.promotions += 1;
+        }
 
-    ((b'a' + x as u8) as char).to_string() + &((y as u8 + b'1') as char).to_string()
+        board.make_move(m);
+
+        let checkers = board.get_checkers();
+        if checkers != 0 {
+            res.checks += 1;
+
+            // a check is direct when given by the piece that just moved (or by the rook that just castled)
+            let mut direct_checker_squares = 0u64;
+            direct_checker_squares.set_square(m.get_to());
+            if m.is_castle_kingside() {
+                direct_checker_squares.set_square(m.get_from() + 1);
+            } else if m.is_castle_queenside() {
+                direct_checker_squares.set_square(m.get_from() - 1);
+            }
+
+            // like the published tables, double checks are not counted as discovered checks
+            if checkers.count_ones() > 1 {
+                res.double_checks += 1;
+            } else if checkers & !direct_checker_squares != 0 {
+                res.discovered_checks += 1;
+            }
+
+            if generate_legal_moves(board, true).len() == 0 {
+                res.checkmates += 1;
+            }
+        }
+
+        board.unmake_move(m);
+    }
+
+    res
 }
 
-pub fn str_to_square(name: &str) -> u64 {
-    let mut chars = name.chars();
+pub fn hashed_perft_test(
+    max_depth: u8,
+    board: &mut Board,
+    num_threads: usize,
+    hash_size_mb: usize,
+    stop: &Arc<AtomicBool>,
+) {
+    println!("---------------------------------------");
+    println!("|                                     |");
+    println!("|  Running Perft test with depth: {:>2}  |", max_depth);
+    println!(
+        "|  Threads: {:>3}    Hash: {:>6} MB    |",
+        num_threads, hash_size_mb
+    );
+    println!("|                                     |");
+    println!("---------------------------------------");
+
+    // the table is shared between depths, so deeper iterations reuse the counts of the shallower ones
+    let table = PerftTable::new(hash_size_mb);
 
-    ((chars.next().unwrap() as u32 - 'a' as u32)
-        + (chars.next().unwrap().to_digit(10).unwrap() - 1) * 8) as u64
+    for i in 1..=max_depth {
+        let now = Instant::now();
+        let num_positions = parallel_perft(i, board, &table, num_threads, stop);
+
+        if stop.load(Ordering::SeqCst) {
+            println!("Stopped during depth {}", i);
+            return;
+        }
+
+        println!(
+            "Depth: {:>2} | Nodes: {:>12} | Time: {}s",
+            i,
+            num_positions,
+            now.elapsed().as_secs_f32()
+        );
+    }
+}
+
+/// Splits the root moves between `num_threads` workers, each one searching with its own copy of the board.
+/// The workers share the same `PerftTable`, so transpositions found by one thread are reused by the others.
+pub fn parallel_perft(
+    depth: u8,
+    board: &Board,
+    table: &PerftTable,
+    num_threads: usize,
+    stop: &Arc<AtomicBool>,
+) -> u128 {
+    let legal_moves = generate_legal_moves(board, true);
+    if depth <= 1 {
+        return if depth == 0 {
+            1
+        } else {
+            legal_moves.len() as u128
+        };
+    }
+
+    let next_move_index = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_threads.max(1))
+            .map(|_| {
+                let mut worker_board = board.clone();
+                let next_move_index = &next_move_index;
+
+                scope.spawn(move || {
+                    let mut res = 0;
+
+                    loop {
+                        let i = next_move_index.fetch_add(1, Ordering::Relaxed);
+                        if i >= legal_moves.len() as usize {
+                            break;
+                        }
+
+                        let m = legal_moves.index(i);
+                        worker_board.make_move(m);
+                        let positions_after_this_move =
+                            hashed_perft_r(depth - 1, &mut worker_board, table, stop);
+                        worker_board.unmake_move(m);
+
+                        #[cfg(debug_assertions)]
+                        {
+                            println!(
+                                "{} : {}",
+                                m.to_long_algebraic_notation(),
+                                positions_after_this_move
+                            );
+                        }
+
+                        res += positions_after_this_move;
+                    }
+
+                    res
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .sum()
+    })
+}
+
+fn hashed_perft_r(
+    depth: u8,
+    board: &mut Board,
+    table: &PerftTable,
+    stop: &Arc<AtomicBool>,
+) -> u128 {
+    if depth == 0 || stop.load(Ordering::Relaxed) {
+        return 1;
+    }
+
+    let legal_moves = generate_legal_moves(board, true);
+    if depth == 1 {
+        return legal_moves.len() as u128;
+    }
+
+    let zobrist_key = board.get_zobrist_key();
+    if let Some(count) = table.probe(zobrist_key, depth) {
+        return count as u128;
+    }
+
+    let mut res = 0;
+    for m in legal_moves.iter() {
+        board.make_move(m);
+        res += hashed_perft_r(depth - 1, board, table, stop);
+        board.unmake_move(m);
+    }
+
+    // a stopped search returns partial counts, which must not end up in the table
+    if !stop.load(Ordering::Relaxed) {
+        table.store(zobrist_key, depth, res as u64);
+    }
+
+    res
+}
+
+/// Each entry is made of two words: `data` packs the node count and the depth (count << 8 | depth),
+/// while `check` holds `key ^ data`. A torn write from another thread makes the xor fail to match the key,
+/// so the entry is simply treated as a miss and no lock is needed.
+#[derive(Default)]
+struct PerftEntry {
+    check: AtomicU64,
+    data: AtomicU64,
+}
+
+pub struct PerftTable {
+    table: Vec<PerftEntry>,
+    size: usize,
+}
+
+impl PerftTable {
+    pub fn new(size_mb: usize) -> Self {
+        let entry_size = std::mem::size_of::<PerftEntry>();
+        let num_entries = ((size_mb * 1024 * 1024) / entry_size).max(1);
+
+        PerftTable {
+            table: (0..num_entries).map(|_| PerftEntry::default()).collect(),
+            size: num_entries,
+        }
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.size as u64) as usize
+    }
+
+    pub fn store(&self, key: u64, depth: u8, count: u64) {
+        let entry = &self.table[self.index(key)];
+        let data = (count << 8) | depth as u64;
+
+        entry.check.store(key ^ data, Ordering::Relaxed);
+        entry.data.store(data, Ordering::Relaxed);
+    }
+
+    pub fn probe(&self, key: u64, depth: u8) -> Option<u64> {
+        let entry = &self.table[self.index(key)];
+        let data = entry.data.load(Ordering::Relaxed);
+        let check = entry.check.load(Ordering::Relaxed);
+
+        if check ^ data == key && (data & 0xFF) as u8 == depth {
+            Some(data >> 8)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    #[test]
+    fn hashed_perft_matches_plain_perft() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut board = Board::new(KIWIPETE);
+        // a small table, so that entries get overwritten by other positions during the count
+        let table = PerftTable::new(1);
+
+        let plain = perft_test_r(4, &mut board, 4, &stop);
+        let hashed = parallel_perft(4, &board, &table, 3, &stop);
+
+        assert_eq!(plain, 4085603);
+        assert_eq!(hashed, plain);
+    }
+
+    fn assert_stats(fen: &str, depth: u8, expected: [u128; 9]) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stats = perft_stats_r(depth, &mut Board::new(fen), &stop);
+
+        let counted = [
+            stats.nodes,
+            stats.captures,
+            stats.enpassants,
+            stats.castles,
+            stats.promotions,
+            stats.checks,
+            stats.discovered_checks,
+            stats.double_checks,
+            stats.checkmates,
+        ];
+        assert_eq!(counted, expected, "{} at depth {}", fen, depth);
+    }
+
+    // nodes, captures, e.p., castles, promotions, checks, discovered checks, double checks, checkmates,
+    // as published on https://www.chessprogramming.org/Perft_Results
+    #[test]
+    fn perft_stats_match_published_tables() {
+        assert_stats(
+            KIWIPETE,
+            4,
+            [4085603, 757163, 1929, 128013, 15172, 25523, 42, 6, 43],
+        );
+        assert_stats(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            5,
+            [674624, 52051, 1165, 0, 0, 52950, 1292, 3, 0],
+        );
+        assert_stats(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            4,
+            [422333, 131393, 0, 7795, 60032, 15492, 19, 0, 5],
+        );
+    }
 }
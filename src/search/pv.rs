@@ -0,0 +1,50 @@
+use super::MAX_PLY;
+use crate::move_gen::chess_move::Move;
+
+/// Triangular PV table: the line at index `ply` holds the best continuation found from that ply,
+/// built by prepending the best move to the line of the child node
+pub struct PvTable {
+    moves: [[Move; MAX_PLY]; MAX_PLY],
+    lengths: [usize; MAX_PLY],
+}
+
+impl PvTable {
+    pub fn new() -> Self {
+        PvTable {
+            moves: [[Move::default(); MAX_PLY]; MAX_PLY],
+            lengths: [0; MAX_PLY],
+        }
+    }
+
+    #[inline]
+    pub fn clear(&mut self, ply: usize) {
+        self.lengths[ply] = 0;
+    }
+
+    pub fn update(&mut self, ply: usize, best_move: Move) {
+        let child_length = if ply + 1 < MAX_PLY {
+            self.lengths[ply + 1].min(MAX_PLY - 1)
+        } else {
+            0
+        };
+
+        self.moves[ply][0] = best_move;
+        if child_length > 0 {
+            let (parent_lines, child_lines) = self.moves.split_at_mut(ply + 1);
+            parent_lines[ply][1..=child_length].copy_from_slice(&child_lines[0][..child_length]);
+        }
+        self.lengths[ply] = child_length + 1;
+    }
+
+    #[inline]
+    pub fn line(&self, ply: usize) -> &[Move] {
+        &self.moves[ply][..self.lengths[ply]]
+    }
+}
+
+pub fn pv_to_string(pv: &[Move]) -> String {
+    pv.iter()
+        .map(|m| m.to_long_algebraic_notation())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
@@ -0,0 +1,174 @@
+use crate::{
+    board::{
+        Board,
+        bitboard::Bitmanip,
+        piece::{PieceColor, PieceType},
+    },
+    move_gen::{attackers_to, chess_move::Move, pins},
+};
+
+// indexed by PieceType, the king is worth more than anything it could win
+pub const SEE_VALUES: [i32; 6] = [500, 330, 900, 300, 100, 20000];
+
+// attackers are tried from the least valuable one
+const ATTACKERS_ORDER: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King,
+];
+
+/// Static exchange evaluation: whether the captures on the landing square of the move, each side
+/// recapturing with its least valuable attacker and free to stop, win at least threshold for the side
+/// to move. A piece pinned to its king doesn't recapture while its pinner is still on the board, and
+/// promotions and castles are only compared to a zero gain.
+pub fn see_ge(board: &Board, m: Move, threshold: i32) -> bool {
+    if m.is_promotion() || m.is_castle_kingside() || m.is_castle_queenside() {
+        return threshold <= 0;
+    }
+
+    let from = m.get_from();
+    let to = m.get_to();
+    let to_bb = 1u64 << to;
+
+    let captured_value = if m.is_enpassant() {
+        SEE_VALUES[PieceType::Pawn]
+    } else {
+        board
+            .get_piece_at(to)
+            .map_or(0, |piece| SEE_VALUES[piece.get_type()])
+    };
+
+    // balance after the move if the opponent doesn't recapture
+    let mut swap = captured_value - threshold;
+    if swap < 0 {
+        return false;
+    }
+
+    // balance if the opponent recaptures the moved piece and the exchange ends there
+    swap = SEE_VALUES[board.get_piece_at(from).unwrap().get_type()] - swap;
+    if swap <= 0 {
+        return true;
+    }
+
+    let pieces_bb = board.get_pieces_bb();
+    let (white_bb, black_bb) = board.get_us_enemy_colors_bb(PieceColor::White);
+    let colors_bb = [white_bb, black_bb];
+
+    let mut occupied_bb = (white_bb | black_bb) ^ (1u64 << from) ^ to_bb;
+    if m.is_enpassant() {
+        // the captured pawn is behind the landing square
+        let captured_square = if board.get_color_to_move() == PieceColor::White {
+            to - 8
+        } else {
+            to + 8
+        };
+        occupied_bb ^= 1u64 << captured_square;
+    }
+    // the landing square is only occupied by whoever captured last, never an attacker itself
+    let mut side_to_move = board.get_color_to_move();
+    let mut res = true;
+
+    // indexed by PieceColor: (pinned pieces, pinners)
+    let pins = [
+        pins(board, PieceColor::White),
+        pins(board, PieceColor::Black),
+    ];
+
+    loop {
+        side_to_move = !side_to_move;
+        let attackers_bb = attackers_to(to_bb, pieces_bb, occupied_bb) & occupied_bb;
+        let mut side_attackers_bb = attackers_bb & colors_bb[side_to_move];
+
+        let (pinned_bb, pinners_bb) = pins[side_to_move];
+        if pinners_bb & occupied_bb != 0 {
+            side_attackers_bb &= !pinned_bb;
+        }
+
+        if side_attackers_bb == 0 {
+            break;
+        }
+
+        res = !res;
+
+        let attacker_type = ATTACKERS_ORDER
+            .into_iter()
+            .find(|&piece_type| side_attackers_bb & pieces_bb[side_to_move][piece_type] != 0)
+            .unwrap();
+
+        if attacker_type == PieceType::King {
+            // the king can only take last, if the other side has no attacker left
+            if attackers_bb & colors_bb[!side_to_move] != 0 {
+                res = !res;
+            }
+            break;
+        }
+
+        swap = SEE_VALUES[attacker_type] - swap;
+        if swap < res as i32 {
+            break;
+        }
+
+        occupied_bb ^= (side_attackers_bb & pieces_bb[side_to_move][attacker_type]).isolate_ls1b();
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::move_gen::generate_legal_moves;
+
+    fn see_ge_move(fen: &str, uci_move: &str, threshold: i32) -> bool {
+        let board = Board::new(fen);
+        let m = generate_legal_moves(&board, true)
+            .iter()
+            .find(|m| m.to_long_algebraic_notation() == uci_move)
+            .unwrap();
+
+        see_ge(&board, m, threshold)
+    }
+
+    #[test]
+    fn xray_through_a_rook_battery() {
+        // Rxd5 Rxd5 Rxd5: the rook on d1 recaptures through the square the first one left
+        let fen = "3r3k/8/8/3p4/8/8/3R4/K2R4 w - - 0 1";
+        assert!(see_ge_move(fen, "d2d5", 100));
+        assert!(!see_ge_move(fen, "d2d5", 101));
+    }
+
+    #[test]
+    fn pinned_attacker_does_not_recapture() {
+        // the knight on f6 is pinned to its king by the bishop on b2
+        let fen = "7k/8/5n2/3p4/8/8/1B6/K2R4 w - - 0 1";
+        assert!(see_ge_move(fen, "d1d5", 100));
+
+        // without the pin the knight takes the rook back
+        let fen = "6k1/8/5n2/3p4/8/8/1B6/K2R4 w - - 0 1";
+        assert!(!see_ge_move(fen, "d1d5", 0));
+        assert!(see_ge_move(fen, "d1d5", -400));
+    }
+
+    #[test]
+    fn promotion_captures_are_compared_to_zero() {
+        let fen = "1r5k/P7/8/8/8/8/8/K7 w - - 0 1";
+        assert!(see_ge_move(fen, "a7b8q", 0));
+        assert!(!see_ge_move(fen, "a7b8q", 1));
+    }
+
+    #[test]
+    fn king_recaptures_last() {
+        // the king takes the rook back when nothing else defends the pawn
+        let fen = "8/8/4k3/3p4/8/8/8/K2R4 w - - 0 1";
+        assert!(!see_ge_move(fen, "d1d5", 0));
+        assert!(see_ge_move(fen, "d1d5", -400));
+
+        // but can't while the bishop on f3 still attacks the square
+        let fen = "8/8/4k3/3p4/8/5B2/8/K2R4 w - - 0 1";
+        assert!(see_ge_move(fen, "d1d5", 100));
+        assert!(!see_ge_move(fen, "d1d5", 101));
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    board::Board,
+    move_gen::{chess_move::Move, generate_legal_moves},
+    rng::Rng,
+};
+
+// positions rich in castling, promotions and en passant, mostly taken from https://www.chessprogramming.org/Perft_Results
+const START_FENS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R w KQkq - 0 1",
+];
+
+/// Plays random games from each of the start positions. After every move the board is checked against
+/// a recomputation from scratch, and every move is also unmade and compared with the previous state
+/// before being made again. Returns the number of games in which a divergence was found.
+pub fn random_games_check(
+    num_games: u32,
+    max_plies: u32,
+    seed: u64,
+    stop: &Arc<AtomicBool>,
+) -> u32 {
+    let mut rng = Rng::new(seed);
+    let mut failed_games = 0;
+    let mut total_plies = 0u64;
+
+    println!(
+        "Running {} random games of up to {} plies (seed {})",
+        num_games, max_plies, seed
+    );
+
+    for game in 0..num_games {
+        if stop.load(Ordering::SeqCst) {
+            println!("Stopped after {} of {} games", game, num_games);
+            break;
+        }
+
+        let start_fen = START_FENS[game as usize % START_FENS.len()];
+        let mut board = Board::new(start_fen);
+        let mut moves_played: Vec<Move> = Vec::with_capacity(max_plies as usize);
+
+        if let Err(error) = board.check_invariants() {
+            report_failure(
+                start_fen,
+                &moves_played,
+                None,
+                &board.to_fen(),
+                "after parsing the FEN",
+                &error,
+            );
+            failed_games += 1;
+            continue;
+        }
+
+        for _ in 0..max_plies {
+            let legal_moves = generate_legal_moves(&board, true);
+            if legal_moves.len() == 0 || board.draw_by_fifty_moves_rule() {
+                break;
+            }
+
+            let m = legal_moves.index(rng.below(legal_moves.len() as u64) as usize);
+            let board_before = board.clone();
+
+            board.make_move(m);
+            if let Err(error) = board.check_invariants() {
+                report_failure(
+                    start_fen,
+                    &moves_played,
+                    Some(m),
+                    &board_before.to_fen(),
+                    "after make_move",
+                    &error,
+                );
+                failed_games += 1;
+                break;
+            }
+
+            board.unmake_move(m);
+            if let Err(error) = board.find_differences(&board_before) {
+                report_failure(
+                    start_fen,
+                    &moves_played,
+                    Some(m),
+                    &board_before.to_fen(),
+                    "after unmake_move",
+                    &error,
+                );
+                failed_games += 1;
+                break;
+            }
+
+            board.make_move(m);
+            moves_played.push(m);
+        }
+
+        total_plies += moves_played.len() as u64;
+    }
+
+    println!(
+        "Checked {} plies, {} game(s) with errors",
+        total_plies, failed_games
+    );
+
+    failed_games
+}
+
+fn report_failure(
+    start_fen: &str,
+    moves_played: &[Move],
+    failing_move: Option<Move>,
+    fen_before_move: &str,
+    when: &str,
+    error: &str,
+) {
+    let moves: Vec<String> = moves_played
+        .iter()
+        .map(|m| m.to_long_algebraic_notation())
+        .collect();
+
+    println!("---------------------------------------");
+    println!(
+        "Inconsistency {} {}",
+        when,
+        failing_move.map_or(String::new(), |m| m.to_long_algebraic_notation())
+    );
+    println!("position fen {} moves {}", start_fen, moves.join(" "));
+    println!("fen before the move: {}", fen_before_move);
+    println!("{}", error);
+    println!("---------------------------------------");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_games_stay_consistent() {
+        let stop = Arc::new(AtomicBool::new(false));
+        assert_eq!(random_games_check(200, 200, 1, &stop), 0);
+    }
+}
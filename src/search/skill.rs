@@ -0,0 +1,136 @@
+use crate::rng::Rng;
+
+use super::{RootLine, SearchOptions, time_manager::SearchLimits};
+
+pub const MAX_SKILL_LEVEL: u8 = 20;
+// UCI_Elo is relative to full strength, which is given this nominal rating rather than one measured
+// against rated opponents
+const FULL_STRENGTH_ELO: i32 = 2400;
+// Elo of skill levels against full strength, from the skillmatch games at 100 ms per move recorded in
+// docs/skill-calibration.md. The levels in between are interpolated.
+const LEVEL_ELO: [(u8, i32); 11] = [
+    (0, -2076),
+    (2, -1694),
+    (4, -1466),
+    (6, -1181),
+    (8, -912),
+    (10, -721),
+    (12, -704),
+    (14, -695),
+    (16, -615),
+    (18, -436),
+    (19, -338),
+];
+// UCI_Elo range, from level 0 to the strongest level below full strength
+pub const MIN_ELO: u32 = (FULL_STRENGTH_ELO + LEVEL_ELO[0].1) as u32;
+pub const MAX_ELO: u32 = (FULL_STRENGTH_ELO + LEVEL_ELO[LEVEL_ELO.len() - 1].1) as u32;
+
+// lines searched to choose from, more if the MultiPV option asks for them
+const SKILL_MULTI_PV: usize = 4;
+// nodes searched at level 0, doubled every 1 / SKILL_NODES_DOUBLING levels
+const SKILL_BASE_NODES: f64 = 1000.0;
+const SKILL_NODES_DOUBLING: f64 = 0.6;
+// the spread of the lines, up to a pawn, sets how much noise is added to their scores
+const SKILL_MAX_SPREAD: i32 = 100;
+
+/// Weakened play: a shallower search limited in nodes, in which the move is picked among the MultiPV lines
+/// with some noise added to their scores, so that a worse move is sometimes chosen on purpose
+pub struct Skill {
+    // 0 to MAX_SKILL_LEVEL, fractional when it comes from an Elo
+    level: f64,
+}
+
+impl Skill {
+    /// None at full strength
+    pub fn from_options(options: &SearchOptions) -> Option<Self> {
+        let level = if options.limit_strength {
+            level_from_elo(options.elo)
+        } else {
+            options.skill_level.min(MAX_SKILL_LEVEL) as f64
+        };
+
+        (level < MAX_SKILL_LEVEL as f64).then_some(Skill { level })
+    }
+
+    pub fn multi_pv(&self, multi_pv: usize) -> usize {
+        multi_pv.max(SKILL_MULTI_PV)
+    }
+
+    /// the limits of the go command, capped in depth and nodes. A search that was infinite stays so, the
+    /// bestmove still waits for the stop.
+    pub fn limit(&self, limits: &SearchLimits) -> SearchLimits {
+        let max_depth = 1 + self.level as u8;
+        let max_nodes = (SKILL_BASE_NODES * (self.level * SKILL_NODES_DOUBLING).exp2()) as u64;
+
+        SearchLimits {
+            depth: Some(limits.depth.map_or(max_depth, |depth| depth.min(max_depth))),
+            nodes: Some(limits.nodes.map_or(max_nodes, |nodes| nodes.min(max_nodes))),
+            infinite: limits.is_infinite(),
+            ..limits.clone()
+        }
+    }
+
+    /// Every line gets a random bonus growing with the weakness, plus a part of how much it is behind the
+    /// best one, and the line with the highest total is played
+    pub(super) fn pick_line<'a>(&self, lines: &'a [RootLine], rng: &mut Rng) -> &'a RootLine {
+        let top_score = lines[0].score;
+        let spread = (top_score - lines[lines.len() - 1].score).min(SKILL_MAX_SPREAD);
+        let weakness = 120 - (2.0 * self.level) as i32;
+
+        let mut best_line = &lines[0];
+        let mut max_score = i32::MIN;
+
+        for line in lines {
+            let push = (weakness * (top_score - line.score)
+                + spread * rng.below(weakness as u64) as i32)
+                / 128;
+
+            if line.score + push >= max_score {
+                max_score = line.score + push;
+                best_line = line;
+            }
+        }
+
+        best_line
+    }
+}
+
+/// the fractional level that plays at this UCI_Elo, between the two measured levels around it
+fn level_from_elo(elo: u32) -> f64 {
+    let elo_difference = elo.clamp(MIN_ELO, MAX_ELO) as i32 - FULL_STRENGTH_ELO;
+
+    LEVEL_ELO
+        .windows(2)
+        .find(|pair| elo_difference <= pair[1].1)
+        .map_or(LEVEL_ELO[LEVEL_ELO.len() - 1].0 as f64, |pair| {
+            let ((low_level, low_elo), (high_level, high_elo)) = (pair[0], pair[1]);
+            low_level as f64
+                + (elo_difference - low_elo) as f64 / (high_elo - low_elo) as f64
+                    * (high_level - low_level) as f64
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elo_maps_onto_the_measured_levels() {
+        assert_eq!(level_from_elo(0), 0.0);
+        assert_eq!(level_from_elo(MIN_ELO), 0.0);
+        assert_eq!(level_from_elo(MAX_ELO), 19.0);
+        assert_eq!(level_from_elo(u32::MAX), 19.0);
+        for (level, elo) in LEVEL_ELO {
+            assert_eq!(
+                level_from_elo((FULL_STRENGTH_ELO + elo) as u32),
+                level as f64
+            );
+        }
+
+        let levels: Vec<f64> = (MIN_ELO..=MAX_ELO)
+            .step_by(10)
+            .map(level_from_elo)
+            .collect();
+        assert!(levels.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::{board::Board, move_gen::generate_legal_moves};
+
+use super::skill::MAX_SKILL_LEVEL;
+
+// short balanced openings, each one played twice with the colors swapped
+const OPENINGS: [&str; 10] = [
+    "e2e4 e7e5 g1f3 b8c6",
+    "e2e4 c7c5 g1f3 d7d6",
+    "e2e4 e7e6 d2d4 d7d5",
+    "e2e4 c7c6 d2d4 d7d5",
+    "d2d4 d7d5 c2c4 e7e6",
+    "d2d4 g8f6 c2c4 g7g6",
+    "d2d4 g8f6 c2c4 e7e6",
+    "c2c4 e7e5 b1c3 g8f6",
+    "g1f3 d7d5 g2g3 g8f6",
+    "e2e4 d7d5 e4d5 d8d5",
+];
+// games still running after this many plies are scored as draws
+const MAX_GAME_PLIES: usize = 400;
+const ENGINE_HASH_MB: usize = 16;
+
+/// Outcome of a game, for the weaker engine
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+/// A copy of this engine run as a child process and driven through UCI
+struct EngineProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl EngineProcess {
+    fn spawn(skill_level: u8) -> io::Result<Self> {
+        let mut child = Command::new(std::env::current_exe()?)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut engine = EngineProcess {
+            child,
+            stdin,
+            stdout,
+        };
+
+        engine.send("uci")?;
+        engine.wait_for("uciok")?;
+        engine.send(&format!("setoption name Hash value {}", ENGINE_HASH_MB))?;
+        engine.send(&format!("setoption name Skill Level value {}", skill_level))?;
+        engine.send("isready")?;
+        engine.wait_for("readyok")?;
+
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    /// reads the output until a line starting with the token, which is returned
+    fn wait_for(&mut self, token: &str) -> io::Result<String> {
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("the engine exited while waiting for {}", token),
+                ));
+            }
+            if line.split_whitespace().next() == Some(token) {
+                return Ok(line);
+            }
+        }
+    }
+
+    fn best_move(&mut self, moves: &[String], move_time_ms: u64) -> io::Result<String> {
+        self.send(&format!("position startpos moves {}", moves.join(" ")))?;
+        self.send(&format!("go movetime {}", move_time_ms))?;
+        let line = self.wait_for("bestmove")?;
+        Ok(line.split_whitespace().nth(1).unwrap_or("").to_string())
+    }
+}
+
+impl Drop for EngineProcess {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.wait();
+    }
+}
+
+/// Plays games between the engine at a skill level and at a stronger one, full strength by default, both
+/// searching for the same time per move, and prints the score of the weaker one with the Elo difference it
+/// implies. Levels too weak to score against full strength are measured against a level in between.
+pub fn play_skill_match(
+    skill_level: u8,
+    opponent_level: u8,
+    num_games: u32,
+    move_time_ms: u64,
+    stop: &Arc<AtomicBool>,
+) {
+    let mut engines = match (
+        EngineProcess::spawn(skill_level),
+        EngineProcess::spawn(opponent_level),
+    ) {
+        (Ok(weak), Ok(full)) => [weak, full],
+        (Err(error), _) | (_, Err(error)) => {
+            println!("info string couldn't start the engines: {}", error);
+            return;
+        }
+    };
+
+    println!(
+        "Playing {} games of skill level {} against {}, {} ms per move",
+        num_games,
+        skill_level,
+        level_name(opponent_level),
+        move_time_ms
+    );
+
+    let mut outcomes: Vec<Outcome> = Vec::with_capacity(num_games as usize);
+
+    for game in 0..num_games {
+        if stop.load(Ordering::SeqCst) {
+            println!("Stopped after {} of {} games", game, num_games);
+            break;
+        }
+
+        let opening = OPENINGS[(game as usize / 2) % OPENINGS.len()];
+        // the weaker engine is white in the even games
+        let weak_is_white = game % 2 == 0;
+
+        match play_game(&mut engines, opening, weak_is_white, move_time_ms) {
+            Ok((outcome, reason)) => {
+                println!(
+                    "Game {:>3}: level {} as {}, {} ({})",
+                    game + 1,
+                    skill_level,
+                    if weak_is_white { "white" } else { "black" },
+                    match outcome {
+                        Outcome::Win => "win",
+                        Outcome::Draw => "draw",
+                        Outcome::Loss => "loss",
+                    },
+                    reason
+                );
+                outcomes.push(outcome);
+            }
+            Err(error) => {
+                println!("info string game {} aborted: {}", game + 1, error);
+                return;
+            }
+        }
+    }
+
+    report_match(skill_level, opponent_level, &outcomes);
+}
+
+fn level_name(level: u8) -> String {
+    if level >= MAX_SKILL_LEVEL {
+        "full strength".to_string()
+    } else {
+        format!("level {}", level)
+    }
+}
+
+/// Returns the outcome for the weaker engine, engines[0], and what ended the game
+fn play_game(
+    engines: &mut [EngineProcess; 2],
+    opening: &str,
+    weak_is_white: bool,
+    move_time_ms: u64,
+) -> io::Result<(Outcome, &'static str)> {
+    let mut board = Board::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+    let mut moves: Vec<String> = Vec::with_capacity(MAX_GAME_PLIES);
+    for engine in engines.iter_mut() {
+        engine.send("ucinewgame")?;
+    }
+
+    let mut weak_to_move = !weak_is_white;
+    let mut opening_moves = opening.split_whitespace();
+
+    loop {
+        // the side to move is the one the game is decided against when it has no legal move
+        weak_to_move = !weak_to_move;
+
+        let legal_moves = generate_legal_moves(&board, true);
+        if legal_moves.len() == 0 {
+            return Ok(match (board.is_in_check(), weak_to_move) {
+                (true, true) => (Outcome::Loss, "checkmate"),
+                (true, false) => (Outcome::Win, "checkmate"),
+                (false, _) => (Outcome::Draw, "stalemate"),
+            });
+        }
+        if board.is_threefold_repetition() {
+            return Ok((Outcome::Draw, "repetition"));
+        }
+        if board.draw_by_fifty_moves_rule() {
+            return Ok((Outcome::Draw, "fifty moves"));
+        }
+        if moves.len() >= MAX_GAME_PLIES {
+            return Ok((Outcome::Draw, "too long"));
+        }
+
+        let uci_move = match opening_moves.next() {
+            Some(m) => m.to_string(),
+            None => engines[if weak_to_move { 0 } else { 1 }].best_move(&moves, move_time_ms)?,
+        };
+
+        let Some(m) = legal_moves
+            .iter()
+            .find(|m| m.to_long_algebraic_notation() == uci_move)
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("illegal move {} after {}", uci_move, moves.join(" ")),
+            ));
+        };
+
+        board.make_move(m);
+        moves.push(uci_move);
+    }
+}
+
+/// Prints the score with the Elo difference it implies and its 95% interval. A score of 0 or 100% has no
+/// finite Elo difference, it is printed as the bound it would be with half a game more or less.
+fn report_match(skill_level: u8, opponent_level: u8, outcomes: &[Outcome]) {
+    if outcomes.is_empty() {
+        return;
+    }
+
+    let count = |outcome| outcomes.iter().filter(|&&o| o == outcome).count();
+    let (wins, draws, losses) = (
+        count(Outcome::Win),
+        count(Outcome::Draw),
+        count(Outcome::Loss),
+    );
+    let num_games = outcomes.len() as f64;
+    let score = (wins as f64 + draws as f64 / 2.0) / num_games;
+
+    let variance = (wins as f64 * (1.0 - score).powi(2)
+        + draws as f64 * (0.5 - score).powi(2)
+        + losses as f64 * score.powi(2))
+        / num_games;
+    let margin = 1.96 * (variance / num_games).sqrt();
+
+    let bounded = |score: f64| score.clamp(0.5 / num_games, 1.0 - 0.5 / num_games);
+    let elo = |score: f64| -400.0 * (1.0 / bounded(score) - 1.0).log10();
+    let elo_difference = if wins + draws == 0 {
+        format!("below {:+.0}", elo(score))
+    } else if draws + losses == 0 {
+        format!("above {:+.0}", elo(score))
+    } else {
+        format!(
+            "{:+.0} ({:+.0} to {:+.0})",
+            elo(score),
+            elo(score - margin),
+            elo(score + margin)
+        )
+    };
+
+    println!(
+        "Level {} vs {}: +{} ={} -{}, score {:.1}%, Elo {}",
+        skill_level,
+        level_name(opponent_level),
+        wins,
+        draws,
+        losses,
+        score * 100.0,
+        elo_difference
+    );
+}
@@ -0,0 +1,55 @@
+use std::fmt::Display;
+
+/// How often a pruning technique was tried and how often it actually pruned
+#[derive(Clone, Copy, Default)]
+pub struct PruningCounter {
+    pub tries: u64,
+    pub hits: u64,
+}
+
+impl PruningCounter {
+    #[inline]
+    pub fn record(&mut self, hit: bool) {
+        self.tries += 1;
+        self.hits += hit as u64;
+    }
+}
+
+impl Display for PruningCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rate = if self.tries == 0 {
+            0.0
+        } else {
+            self.hits as f64 * 100.0 / self.tries as f64
+        };
+        write!(f, "{}/{} ({:.1}%)", self.hits, self.tries, rate)
+    }
+}
+
+/// Hit rates of the pruning techniques over a whole search, reported with info string when it ends in debug mode
+#[derive(Clone, Copy, Default)]
+pub struct PruningStats {
+    pub reverse_futility: PruningCounter,
+    pub razoring: PruningCounter,
+    pub null_move: PruningCounter,
+    pub futility: PruningCounter,
+    pub late_move: PruningCounter,
+    pub delta: PruningCounter,
+    pub see: PruningCounter,
+}
+
+impl Display for PruningStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "rfp {} razoring {} nmp {} futility {} lmp {} delta {} see {}",
+            self.reverse_futility,
+            self.razoring,
+            self.null_move,
+            self.futility,
+            self.late_move,
+            self.delta,
+            self.see
+        )
+    }
+}
@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use crate::{board::piece::PieceColor, move_gen::chess_move::Move};
+
+// moves the remaining time is split into when the gui doesn't say, also the most it's ever split into
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+// the increment that will come with the next moves, counted in the optimum time
+const INCREMENT_PERCENT: u64 = 75;
+// the hard limit is this many times the optimum time, and never more than this percent of the clock
+const MAXIMUM_OPTIMUM_RATIO: u64 = 5;
+const MAXIMUM_CLOCK_PERCENT: u64 = 80;
+// optimum time added when the Ponder option is on, as part of the thinking happens on the opponent's time
+const PONDER_BONUS_PERCENT: u64 = 25;
+
+// the soft limit is scaled by STABILITY_FACTOR_MAX when the best move just changed, and by
+// STABILITY_FACTOR_STEP less for every iteration it stays the same, down to STABILITY_FACTOR_MIN
+const STABILITY_FACTOR_MAX: f64 = 1.6;
+const STABILITY_FACTOR_STEP: f64 = 0.15;
+const STABILITY_FACTOR_MIN: f64 = 0.7;
+// a score that dropped since the previous iteration scales it up by 1% per centipawn, up to double
+const SCORE_DROP_MAX: i32 = 100;
+
+/// Limits given with the go command
+#[derive(Clone, Default)]
+pub struct SearchLimits {
+    // indexed by PieceColor
+    pub time: [Option<u64>; 2],
+    pub increment: [u64; 2],
+    pub moves_to_go: Option<u64>,
+    pub move_time: Option<u64>,
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub mate: Option<u32>,
+    pub infinite: bool,
+    pub ponder: bool,
+    // the only root moves searched, all of them when empty
+    pub search_moves: Vec<Move>,
+}
+
+impl SearchLimits {
+    /// a go without any limit searches until stop
+    pub fn is_infinite(&self) -> bool {
+        self.infinite
+            || (self.time.iter().all(Option::is_none)
+                && self.move_time.is_none()
+                && self.depth.is_none()
+                && self.nodes.is_none()
+                && self.mate.is_none())
+    }
+}
+
+/// Splits the clock between moves: the search is never longer than the maximum time, and no new iteration
+/// is started once the optimum time, scaled by how stable the search looks, has passed
+pub struct TimeManager {
+    optimum: Option<Duration>,
+    maximum: Option<Duration>,
+    previous_best_move: Option<Move>,
+    stable_iterations: u32,
+    previous_score: Option<i32>,
+}
+
+impl TimeManager {
+    pub fn new(
+        limits: &SearchLimits,
+        color: PieceColor,
+        move_overhead_ms: u64,
+        ponder: bool,
+    ) -> Self {
+        let (optimum_ms, maximum_ms) = if limits.infinite {
+            (None, None)
+        } else if let Some(move_time) = limits.move_time {
+            // the whole move time is used, there is nothing to gain by stopping earlier
+            (
+                None,
+                Some(move_time.saturating_sub(move_overhead_ms).max(1)),
+            )
+        } else if let Some(time) = limits.time[color].or(limits.time.into_iter().flatten().next()) {
+            // the clock of the opponent is used when the gui only sends that one, rather than no limit. The
+            // overhead is paid on every move left, and something is always kept on the clock
+            let moves_to_go = limits.moves_to_go.map_or(DEFAULT_MOVES_TO_GO, |moves| {
+                moves.clamp(1, DEFAULT_MOVES_TO_GO)
+            });
+            let time_left = time.saturating_sub(move_overhead_ms).max(1);
+            let maximum = (time_left * MAXIMUM_CLOCK_PERCENT / 100).max(1);
+            let mut optimum = (time_left / moves_to_go
+                + limits.increment[color] * INCREMENT_PERCENT / 100)
+                .max(1);
+            if ponder {
+                optimum += optimum * PONDER_BONUS_PERCENT / 100;
+            }
+
+            (
+                Some(optimum.min(maximum)),
+                Some((optimum * MAXIMUM_OPTIMUM_RATIO).min(maximum)),
+            )
+        } else {
+            (None, None)
+        };
+
+        TimeManager {
+            optimum: optimum_ms.map(Duration::from_millis),
+            maximum: maximum_ms.map(Duration::from_millis),
+            previous_best_move: None,
+            stable_iterations: 0,
+            previous_score: None,
+        }
+    }
+
+    /// the search is aborted, even in the middle of an iteration, once this has passed
+    pub fn hard_limit(&self) -> Option<Duration> {
+        self.maximum
+    }
+
+    /// Called after every completed iteration with the time used so far, tells whether the next one should
+    /// be started
+    pub fn should_stop_after_iteration(
+        &mut self,
+        best_move: Move,
+        score: i32,
+        elapsed: Duration,
+    ) -> bool {
+        if self.previous_best_move == Some(best_move) {
+            self.stable_iterations += 1;
+        } else {
+            self.stable_iterations = 0;
+        }
+        self.previous_best_move = Some(best_move);
+
+        let score_drop = self
+            .previous_score
+            .map_or(0, |previous| (previous - score).clamp(0, SCORE_DROP_MAX));
+        self.previous_score = Some(score);
+
+        let Some(optimum) = self.optimum else {
+            return false;
+        };
+
+        let stability_factor = (STABILITY_FACTOR_MAX
+            - STABILITY_FACTOR_STEP * self.stable_iterations as f64)
+            .max(STABILITY_FACTOR_MIN);
+        let score_drop_factor = 1.0 + score_drop as f64 / SCORE_DROP_MAX as f64;
+        let soft_limit = optimum.mul_f64(stability_factor * score_drop_factor);
+
+        elapsed >= soft_limit.min(self.maximum.unwrap_or(Duration::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_clock_still_gets_a_time_budget() {
+        for (time, moves_to_go) in [(5, None), (30, Some(40)), (1, Some(1))] {
+            let mut limits = SearchLimits {
+                moves_to_go,
+                ..Default::default()
+            };
+            limits.time[PieceColor::White] = Some(time);
+            let time_manager = TimeManager::new(&limits, PieceColor::White, 0, false);
+
+            assert!(time_manager.optimum.unwrap() >= Duration::from_millis(1));
+            assert!(time_manager.hard_limit().unwrap() >= Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn clock_of_the_opponent_only_still_limits_the_search() {
+        let mut limits = SearchLimits::default();
+        limits.time[PieceColor::White] = Some(1000);
+        let time_manager = TimeManager::new(&limits, PieceColor::Black, 0, false);
+
+        assert!(time_manager.hard_limit().unwrap() <= Duration::from_millis(1000));
+    }
+}
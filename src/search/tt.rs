@@ -1,6 +1,12 @@
-use crate::Move;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    sync::atomic::{AtomicU16, AtomicU64, Ordering},
+};
 
-#[derive(Clone, Copy)]
+use crate::{Move, board::zobrist};
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum EntryType {
     Exact,
     LowerBound,
@@ -9,80 +15,410 @@ pub enum EntryType {
 
 #[derive(Clone, Copy)]
 pub struct TTEntry {
-    key: u64,
     pub depth: u8,
     pub score: i32,
+    pub static_eval: Option<i32>,
     pub entry_type: EntryType,
     pub best_move: Option<Move>,
     age: u8,
 }
 
+const ENTRIES_PER_CLUSTER: usize = 3;
+// the age is stored in 6 bits and wraps around
+const AGE_MASK: u8 = 0x3F;
+// static eval of the positions in check, which have none
+const NO_STATIC_EVAL: i16 = i16::MIN;
+// an entry this many searches old is worth one ply of depth less when choosing which one to replace
+const AGE_DEPTH_PENALTY: i32 = 8;
+// an exact score may replace a deeper bound of the same position and search when it is at most this much
+// shallower, so that a quiescence result can only replace the one of a very shallow search
+const EXACT_REPLACE_DEPTH_GAP: u8 = 2;
+
+// saved tables start with the magic, the format version, the checksum of the zobrist keys, the number of
+// clusters and the age, followed by the data and verification keys of every cluster, all little endian
+const FILE_MAGIC: &[u8; 8] = b"CETTABLE";
+const FILE_VERSION: u32 = 1;
+const FILE_HEADER_SIZE: u64 = 8 + 4 + 8 + 8 + 1;
+const FILE_CLUSTER_SIZE: u64 = ENTRIES_PER_CLUSTER as u64 * (8 + 2);
+
+impl TTEntry {
+    // data layout: best move in bits 0-15 (0 if none), score in 16-31, static eval in 32-47,
+    // depth in 48-55, entry type + 1 in 56-57 so that an empty entry is all zeros, age in 58-63
+    fn pack(self) -> u64 {
+        let entry_type = match self.entry_type {
+            EntryType::Exact => 1u64,
+            EntryType::LowerBound => 2,
+            EntryType::UpperBound => 3,
+        };
+        let static_eval = self.static_eval.map_or(NO_STATIC_EVAL, |eval| eval as i16);
+
+        self.best_move.map_or(0, u16::from) as u64
+            | (self.score as i16 as u16 as u64) << 16
+            | (static_eval as u16 as u64) << 32
+            | (self.depth as u64) << 48
+            | entry_type << 56
+            | ((self.age & AGE_MASK) as u64) << 58
+    }
+
+    fn unpack(data: u64) -> Option<Self> {
+        let entry_type = match (data >> 56) & 0b11 {
+            1 => EntryType::Exact,
+            2 => EntryType::LowerBound,
+            3 => EntryType::UpperBound,
+            _ => return None,
+        };
+        let best_move = data as u16;
+        let static_eval = (data >> 32) as u16 as i16;
+
+        Some(TTEntry {
+            depth: (data >> 48) as u8,
+            score: (data >> 16) as u16 as i16 as i32,
+            static_eval: (static_eval != NO_STATIC_EVAL).then_some(static_eval as i32),
+            entry_type,
+            best_move: (best_move != 0).then(|| Move::from(best_move)),
+            age: (data >> 58) as u8,
+        })
+    }
+}
+
+/// Three 10 bytes entries in 32 bytes, so that a probe touches a single cache line. Each entry is its packed
+/// data and the low 16 bits of its key xored with a fold of the data: an entry torn by two threads storing
+/// at the same time doesn't verify and is ignored, without locking.
+#[repr(C, align(32))]
+#[derive(Default)]
+struct Cluster {
+    data: [AtomicU64; ENTRIES_PER_CLUSTER],
+    keys: [AtomicU16; ENTRIES_PER_CLUSTER],
+    _padding: u16,
+}
+
+#[inline]
+fn verification_key(key: u64, data: u64) -> u16 {
+    key as u16 ^ (data ^ data >> 16 ^ data >> 32 ^ data >> 48) as u16
+}
+
+/// Transposition table shared by all the search threads
 pub struct TranspositionTable {
-    table: Vec<Option<TTEntry>>,
-    size: usize,
+    clusters: Vec<Cluster>,
     age: u8,
 }
 
 impl TranspositionTable {
     pub fn new(size_mb: usize) -> Self {
-        let entry_size = std::mem::size_of::<TTEntry>();
-        let num_entries = (size_mb * 1024 * 1024) / entry_size;
+        let cluster_size = std::mem::size_of::<Cluster>();
+        Self::with_clusters(((size_mb * 1024 * 1024) / cluster_size).max(1))
+    }
 
+    fn with_clusters(num_clusters: usize) -> Self {
         TranspositionTable {
-            table: vec![None; num_entries],
-            size: num_entries,
+            clusters: (0..num_clusters).map(|_| Cluster::default()).collect(),
             age: 0,
         }
     }
 
+    /// the old table is freed before the new one is allocated, so that both never take memory together
+    pub fn resize(&mut self, size_mb: usize) {
+        self.clusters = Vec::new();
+        *self = TranspositionTable::new(size_mb);
+    }
+
+    /// size in MB, as given to new
+    pub fn size_mb(&self) -> usize {
+        self.clusters.len() * std::mem::size_of::<Cluster>() / (1024 * 1024)
+    }
+
+    /// empties the table, for a new game
+    pub fn clear(&mut self) {
+        for cluster in self.clusters.iter_mut() {
+            *cluster = Cluster::default();
+        }
+        self.age = 0;
+    }
+
+    /// called once at the start of every search, so that the entries of the previous ones get replaced first
     pub fn increment_age(&mut self) {
-        self.age = self.age.wrapping_add(1);
+        self.age = self.age.wrapping_add(1) & AGE_MASK;
+    }
+
+    /// multiply-shift: maps the high bits of the key to [0, number of clusters) without a division,
+    /// leaving the low bits for the verification key
+    #[inline]
+    fn cluster(&self, key: u64) -> &Cluster {
+        let index = ((key as u128 * self.clusters.len() as u128) >> 64) as usize;
+        &self.clusters[index]
     }
 
-    fn index(&self, key: u64) -> usize {
-        (key % self.size as u64) as usize
+    /// starts loading the cluster of the key into the cache, to be called as soon as the key is known
+    #[inline]
+    pub fn prefetch(&self, key: u64) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            use std::arch::x86_64::{_MM_HINT_T0, _mm_prefetch};
+
+            let cluster: *const Cluster = self.cluster(key);
+            // prefetching has no observable effect besides the cache, any address is fine
+            unsafe { _mm_prefetch::<_MM_HINT_T0>(cluster as *const i8) };
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = key;
+    }
+
+    #[inline]
+    fn load_entry(cluster: &Cluster, i: usize, key: u64) -> Option<TTEntry> {
+        let data = cluster.data[i].load(Ordering::Relaxed);
+        let verification = cluster.keys[i].load(Ordering::Relaxed);
+
+        if verification == verification_key(key, data) {
+            TTEntry::unpack(data)
+        } else {
+            None
+        }
     }
 
     pub fn store(
-        &mut self,
+        &self,
         key: u64,
         depth: u8,
         score: i32,
+        static_eval: Option<i32>,
         entry_type: EntryType,
         best_move: Option<Move>,
     ) {
-        let idx = self.index(key);
-
-        // replacement strategy: replace if
-        // 1) None
-        // 2) new entry is deeper
-        // 3) old entry is older
-        if let Some(existing) = &self.table[idx] {
-            if existing.depth > depth && existing.age == self.age {
+        let cluster = self.cluster(key);
+
+        // replacement strategy: the entry of the same position if there is one, otherwise the entry
+        // with the lowest depth, counting older entries as shallower
+        let mut replace_index = 0;
+        let mut replace_value = i32::MAX;
+        let mut existing_entry = None;
+
+        for i in 0..ENTRIES_PER_CLUSTER {
+            if let Some(entry) = Self::load_entry(cluster, i, key) {
+                replace_index = i;
+                existing_entry = Some(entry);
+                break;
+            }
+
+            let value = match TTEntry::unpack(cluster.data[i].load(Ordering::Relaxed)) {
+                None => i32::MIN,
+                Some(entry) => {
+                    let relative_age = (self.age.wrapping_sub(entry.age) & AGE_MASK) as i32;
+                    entry.depth as i32 - AGE_DEPTH_PENALTY * relative_age
+                }
+            };
+
+            if value < replace_value {
+                replace_value = value;
+                replace_index = i;
+            }
+        }
+
+        let mut best_move = best_move;
+        if let Some(existing) = existing_entry {
+            // a deeper result of the current search is only overwritten by an exact score of a close depth
+            if existing.depth > depth
+                && existing.age == self.age
+                && (entry_type != EntryType::Exact
+                    || existing.depth > depth + EXACT_REPLACE_DEPTH_GAP)
+            {
                 return;
             }
+
+            // keep the move of a previous search of this position rather than none
+            best_move = best_move.or(existing.best_move);
         }
 
-        self.table[idx] = Some(TTEntry {
-            key,
+        let data = TTEntry {
             depth,
             score,
+            static_eval,
             entry_type,
             best_move,
             age: self.age,
-        });
+        }
+        .pack();
+
+        cluster.data[replace_index].store(data, Ordering::Relaxed);
+        cluster.keys[replace_index].store(verification_key(key, data), Ordering::Relaxed);
+    }
+
+    /// permille of the table used by the current search, estimated on the first 1000 clusters
+    pub fn hashfull(&self) -> usize {
+        let sample_size = self.clusters.len().min(1000);
+        let used = self.clusters[..sample_size]
+            .iter()
+            .flat_map(|cluster| cluster.data.iter())
+            .filter_map(|data| TTEntry::unpack(data.load(Ordering::Relaxed)))
+            .filter(|entry| entry.age == self.age)
+            .count();
+
+        used * 1000 / (sample_size * ENTRIES_PER_CLUSTER)
     }
 
     #[inline]
-    pub fn probe(&self, key: u64) -> Option<&TTEntry> {
-        let idx = self.index(key);
+    pub fn probe(&self, key: u64) -> Option<TTEntry> {
+        let cluster = self.cluster(key);
+
+        (0..ENTRIES_PER_CLUSTER).find_map(|i| Self::load_entry(cluster, i, key))
+    }
+
+    /// writes the whole table to a file that load can read back, even from another process
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("can't create {}: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+
+        let mut write = |bytes: &[u8]| {
+            writer
+                .write_all(bytes)
+                .map_err(|e| format!("can't write {}: {}", path, e))
+        };
+
+        write(FILE_MAGIC)?;
+        write(&FILE_VERSION.to_le_bytes())?;
+        write(&zobrist::keys_checksum().to_le_bytes())?;
+        write(&(self.clusters.len() as u64).to_le_bytes())?;
+        write(&[self.age])?;
+
+        for cluster in self.clusters.iter() {
+            for data in cluster.data.iter() {
+                write(&data.load(Ordering::Relaxed).to_le_bytes())?;
+            }
+            for key in cluster.keys.iter() {
+                write(&key.load(Ordering::Relaxed).to_le_bytes())?;
+            }
+        }
+
+        writer
+            .flush()
+            .map_err(|e| format!("can't write {}: {}", path, e))
+    }
+
+    /// reads a table written by save, which keeps the size it was saved with. Files of another format or
+    /// hashed with other zobrist keys are rejected.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("can't open {}: {}", path, e))?;
+        let file_size = file
+            .metadata()
+            .map_err(|e| format!("can't read {}: {}", path, e))?
+            .len();
+        let mut reader = BufReader::new(file);
+
+        if &read_bytes::<8>(&mut reader, path)? != FILE_MAGIC {
+            return Err(format!("{} is not a saved transposition table", path));
+        }
+
+        let version = u32::from_le_bytes(read_bytes(&mut reader, path)?);
+        if version != FILE_VERSION {
+            return Err(format!(
+                "{} has format version {}, expected {}",
+                path, version, FILE_VERSION
+            ));
+        }
+
+        if u64::from_le_bytes(read_bytes(&mut reader, path)?) != zobrist::keys_checksum() {
+            return Err(format!("{} was saved with different zobrist keys", path));
+        }
 
-        if let Some(entry) = &self.table[idx] {
-            if entry.key == key {
-                return Some(entry);
+        let num_clusters = u64::from_le_bytes(read_bytes(&mut reader, path)?);
+        let expected_size = num_clusters
+            .checked_mul(FILE_CLUSTER_SIZE)
+            .and_then(|size| size.checked_add(FILE_HEADER_SIZE));
+        if num_clusters == 0 || expected_size != Some(file_size) {
+            return Err(format!(
+                "{} is {} bytes long, which doesn't match its {} clusters",
+                path, file_size, num_clusters
+            ));
+        }
+
+        let [age] = read_bytes(&mut reader, path)?;
+        if age > AGE_MASK {
+            return Err(format!("{} has an invalid age {}", path, age));
+        }
+
+        let mut table = Self::with_clusters(num_clusters as usize);
+        table.age = age;
+
+        for cluster in table.clusters.iter_mut() {
+            let cluster_bytes = read_bytes::<{ FILE_CLUSTER_SIZE as usize }>(&mut reader, path)?;
+
+            let (data_bytes, key_bytes) = cluster_bytes.split_at(ENTRIES_PER_CLUSTER * 8);
+            for (data, bytes) in cluster.data.iter_mut().zip(data_bytes.chunks_exact(8)) {
+                *data.get_mut() = u64::from_le_bytes(bytes.try_into().unwrap());
             }
+            for (key, bytes) in cluster.keys.iter_mut().zip(key_bytes.chunks_exact(2)) {
+                *key.get_mut() = u16::from_le_bytes(bytes.try_into().unwrap());
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read, path: &str) -> Result<[u8; N], String> {
+    let mut bytes = [0u8; N];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|e| format!("can't read {}: {}", path, e))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("{}_{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let mut table = TranspositionTable::new(1);
+        table.increment_age();
+        let keys: Vec<u64> = (1..=1000u64)
+            .map(|i| i.wrapping_mul(0x9E3779B97F4A7C15))
+            .collect();
+        for (i, &key) in keys.iter().enumerate() {
+            let best_move = Some(Move::from(0x1234u16 + i as u16));
+            table.store(
+                key,
+                5,
+                i as i32 - 500,
+                Some(17),
+                EntryType::LowerBound,
+                best_move,
+            );
         }
 
-        None
+        let path = temp_path("tt_round_trip");
+        table.save(&path).unwrap();
+        let loaded = TranspositionTable::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.size_mb(), table.size_mb());
+        assert_eq!(loaded.age, table.age);
+        for &key in keys.iter() {
+            let (saved, loaded) = (table.probe(key), loaded.probe(key));
+            assert_eq!(saved.map(TTEntry::pack), loaded.map(TTEntry::pack));
+        }
+    }
+
+    #[test]
+    fn load_rejects_an_overflowing_cluster_count() {
+        let path = temp_path("tt_overflow");
+        let mut header = Vec::new();
+        header.extend_from_slice(FILE_MAGIC);
+        header.extend_from_slice(&FILE_VERSION.to_le_bytes());
+        header.extend_from_slice(&zobrist::keys_checksum().to_le_bytes());
+        header.extend_from_slice(&(u64::MAX / 2).to_le_bytes());
+        header.push(0);
+        std::fs::write(&path, header).unwrap();
+
+        let loaded = TranspositionTable::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
     }
 }